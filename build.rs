@@ -0,0 +1,28 @@
+//! Select the directory the vDSO image is embedded from, see `src/embed.rs`.
+
+use std::{env, path::PathBuf};
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=STARRY_VDSO_PATH");
+    let manifest_dir = PathBuf::from(env::var_os("CARGO_MANIFEST_DIR").unwrap());
+    let arch = env::var("CARGO_CFG_TARGET_ARCH").unwrap();
+    // A relative override is taken relative to this crate, like the default.
+    let dir = match env::var_os("STARRY_VDSO_PATH") {
+        Some(dir) => {
+            let dir = manifest_dir.join(dir);
+            let image = dir.join(format!("vdso_{arch}.so"));
+            assert!(
+                image.is_file(),
+                "STARRY_VDSO_PATH: {} not found",
+                image.display()
+            );
+            dir
+        }
+        None => manifest_dir.join("vdso"),
+    };
+    println!(
+        "cargo:rerun-if-changed={}",
+        dir.join(format!("vdso_{arch}.so")).display()
+    );
+    println!("cargo:rustc-env=STARRY_VDSO_DIR={}", dir.display());
+}
@@ -1,7 +1,48 @@
 pub const VVAR_PAGES: usize = 4;
 
+/// Number of clock bases in `VdsoClock::time_data` (`CLOCK_TAI + 1` in the
+/// kernel headers the bundled vDSO was built against).
+pub const VDSO_BASES: usize = 12;
+
+/// Valid bits of the hardware counter read by the vDSO.
+///
+/// The architecture only guarantees a 56-bit generic timer counter before
+/// ARMv8.6 (Linux `CLOCKSOURCE_MASK(56)` in
+/// `drivers/clocksource/arm_arch_timer.c`).
+pub const COUNTER_MASK: u64 = (1 << 56) - 1;
+
 #[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ClockMode {
     None,
     Cntvct,
 }
+
+impl ClockMode {
+    /// Convert a raw `clock_mode` value back to a known mode.
+    pub const fn from_raw(mode: i32) -> Option<Self> {
+        match mode {
+            0 => Some(Self::None),
+            1 => Some(Self::Cntvct),
+            _ => None,
+        }
+    }
+}
+
+// Values shared with the userspace vDSO (`VDSO_CLOCKMODE_NONE` and
+// `VDSO_CLOCKMODE_ARCHTIMER`).
+const _: () = assert!(ClockMode::None as i32 == 0);
+const _: () = assert!(ClockMode::Cntvct as i32 == 1);
+
+/// Generic timer counter the vDSO reads from EL0.
+///
+/// The bundled `vdso_aarch64.so` reads `CNTVCT_EL0`; selecting the physical
+/// counter only makes sense with a vDSO built to read `CNTPCT_EL0`.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aarch64Counter {
+    /// `CNTPCT_EL0`, opened to EL0 by `CNTKCTL_EL1.EL0PCTEN`.
+    Physical = 0,
+    /// `CNTVCT_EL0`, opened to EL0 by `CNTKCTL_EL1.EL0VCTEN`.
+    Virtual  = 1,
+}
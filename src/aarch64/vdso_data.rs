@@ -1,12 +1,91 @@
-use crate::vdso_time_data::VdsoTimeData;
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicU64, Ordering};
+
+use axplat::time::{NANOS_PER_SEC, nanos_to_ticks};
+
+pub use crate::vdso_time_data::authoritative_counter_hz;
+use crate::{
+    config::{Aarch64Counter, VVAR_PAGES},
+    vdso::VvarLayout,
+    vdso_time_data::{VdsoClock, VdsoTimeData},
+};
+
+/// `CNTKCTL_EL1.EL0PCTEN`: EL0 access to the physical counter.
+const CNTKCTL_EL0PCTEN: u64 = 1 << 0;
+/// `CNTKCTL_EL1.EL0VCTEN`: EL0 access to the virtual counter.
+const CNTKCTL_EL0VCTEN: u64 = 1 << 1;
+
+/// Counter selected by [`set_aarch64_counter`].
+static AARCH64_COUNTER: AtomicU8 = AtomicU8::new(Aarch64Counter::Virtual as u8);
+
+/// Select which counter [`enable_cntvct_access`] opens to userspace.
+/// Defaults to [`Aarch64Counter::Virtual`].
+pub fn set_aarch64_counter(counter: Aarch64Counter) {
+    AARCH64_COUNTER.store(counter as u8, Ordering::Relaxed);
+}
+
+/// Get the counter the vDSO is expected to read.
+pub fn aarch64_counter() -> Aarch64Counter {
+    match AARCH64_COUNTER.load(Ordering::Relaxed) {
+        0 => Aarch64Counter::Physical,
+        _ => Aarch64Counter::Virtual,
+    }
+}
+
+/// Whether userspace must issue an `isb` before reading the counter.
+static COUNTER_NEEDS_ISB: AtomicBool = AtomicBool::new(true);
+
+/// Set the counter-read hints published in the arch data page: which counter
+/// register userspace reads and whether an `isb` must precede the read.
+pub fn set_arch_read_hints(counter: Aarch64Counter, needs_isb: bool) {
+    set_aarch64_counter(counter);
+    COUNTER_NEEDS_ISB.store(needs_isb, Ordering::Relaxed);
+}
+
+/// Counter-read hints for the userspace vDSO, at the start of the arch data
+/// page. The bundled vDSO ignores them and always reads `CNTVCT_EL0` after an
+/// `isb`; custom builds can use them to follow the kernel's selection.
+#[repr(C)]
+pub struct VdsoArchData {
+    /// Counter register to read, an [`Aarch64Counter`] value.
+    pub counter: u32,
+    /// Non-zero if an `isb` is required before reading the counter.
+    pub needs_isb: u32,
+    /// Non-zero if `CNTVCT_EL0` may spuriously read 0 and a zero read must
+    /// be retried, see [`set_counter_workaround`].
+    pub counter_workaround: u32,
+    _reserved: [u8; 4096 - 12],
+}
+
+impl VdsoArchData {
+    pub const fn new() -> Self {
+        Self {
+            counter: Aarch64Counter::Virtual as u32,
+            needs_isb: 1,
+            counter_workaround: 0,
+            _reserved: [0; 4096 - 12],
+        }
+    }
+}
+
+impl Default for VdsoArchData {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[repr(C)]
 pub struct VdsoData {
     pub time_data: VdsoTimeData,
     pub timen_data: [u8; 4096],
     pub rng_data: [u8; 4096],
-    pub arch_data: [u8; 4096],
+    pub arch_data: VdsoArchData,
 }
 
+// The data page is mapped to userspace, so it must start on a page boundary.
+const _: () = assert!(core::mem::align_of::<VdsoData>() >= 4096);
+// `VdsoClock` layout read by the bundled vDSO: no `max_cycles` field.
+const _: () = assert!(core::mem::size_of::<VdsoClock>() == 232);
+
 impl Default for VdsoData {
     fn default() -> Self {
         Self::new()
@@ -14,27 +93,167 @@ impl Default for VdsoData {
 }
 
 impl VdsoData {
+    pub const VVAR_LAYOUT: VvarLayout = VvarLayout {
+        total_pages: VVAR_PAGES,
+        backed_pages: core::mem::size_of::<Self>() / 4096,
+        time_data: core::mem::offset_of!(Self, time_data) / 4096,
+        timens: core::mem::offset_of!(Self, timen_data) / 4096,
+        rng: core::mem::offset_of!(Self, rng_data) / 4096,
+        arch: core::mem::offset_of!(Self, arch_data) / 4096,
+        pvclock: None,
+    };
+
     pub const fn new() -> Self {
         Self {
             time_data: VdsoTimeData::new(),
             timen_data: [0u8; 4096],
             rng_data: [0u8; 4096],
-            arch_data: [0u8; 4096],
+            arch_data: VdsoArchData::new(),
         }
     }
 
     pub fn time_update(&mut self) {
+        self.arch_data.counter = aarch64_counter() as u32;
+        self.arch_data.needs_isb = COUNTER_NEEDS_ISB.load(Ordering::Relaxed) as u32;
+        self.arch_data.counter_workaround = counter_workaround_active() as u32;
         self.time_data.update();
     }
 }
 
+/// Whether `CNTVCT_EL0` reads go through [`read_cntvct_safe`].
+static COUNTER_WORKAROUND: AtomicBool = AtomicBool::new(false);
+
+/// Enable the workaround for cores whose `CNTVCT_EL0` intermittently reads
+/// 0. Also published to userspace in [`VdsoArchData::counter_workaround`]
+/// with the next update.
+pub fn set_counter_workaround(enabled: bool) {
+    COUNTER_WORKAROUND.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether the zero-read workaround is enabled.
+pub fn counter_workaround_active() -> bool {
+    COUNTER_WORKAROUND.load(Ordering::Relaxed)
+}
+
+/// Read `CNTVCT_EL0` after an `isb`.
+fn read_cntvct() -> u64 {
+    let counter: u64;
+    unsafe {
+        core::arch::asm!("isb", "mrs {}, CNTVCT_EL0", out(reg) counter, options(nostack));
+    }
+    counter
+}
+
+/// Read `CNTVCT_EL0`, reading again if the first read returned a spurious 0.
+pub fn read_cntvct_safe() -> u64 {
+    match read_cntvct() {
+        0 => read_cntvct(),
+        counter => counter,
+    }
+}
+
+/// Read the generic timer counter selected by [`set_aarch64_counter`], with
+/// the `isb` that keeps the read from being speculated early.
+pub fn read_arch_counter() -> u64 {
+    match aarch64_counter() {
+        Aarch64Counter::Physical => {
+            let counter: u64;
+            unsafe {
+                core::arch::asm!("isb", "mrs {}, CNTPCT_EL0", out(reg) counter, options(nostack));
+            }
+            counter
+        }
+        Aarch64Counter::Virtual if counter_workaround_active() => read_cntvct_safe(),
+        Aarch64Counter::Virtual => read_cntvct(),
+    }
+}
+
+/// Read the counter as the vDSO's `__arch_get_hw_counter` does: the `isb`
+/// of [`read_arch_counter`], then Linux `arch_counter_enforce_ordering`.
+///
+/// The `isb` keeps the read from being speculated before the seqlock load
+/// of `cycle_last`. The dummy load that depends on the counter value keeps
+/// the seqlock retry check from being satisfied before the counter is read.
+/// Without either, a reader can pair a counter value with bases from
+/// another update and see time go backwards.
+pub fn read_hw_counter_fenced() -> u64 {
+    let counter = read_arch_counter();
+    unsafe {
+        core::arch::asm!(
+            "eor {tmp}, {cnt}, {cnt}",
+            "add {tmp}, sp, {tmp}",
+            "ldr xzr, [{tmp}]",
+            tmp = out(reg) _,
+            cnt = in(reg) counter,
+            options(nostack, readonly),
+        );
+    }
+    counter
+}
+
+/// `CNTFRQ_EL0` seen by the last [`check_cntfrq`], 0 before the first check.
+static DETECTED_CNTFRQ: AtomicU64 = AtomicU64::new(0);
+
+/// Read `CNTFRQ_EL0`, the counter frequency firmware programmed on this CPU.
+pub fn read_cntfrq() -> u64 {
+    let freq: u64;
+    unsafe {
+        core::arch::asm!("mrs {}, CNTFRQ_EL0", out(reg) freq, options(nomem, nostack));
+    }
+    freq
+}
+
+/// Compare this CPU's `CNTFRQ_EL0` against the platform counter frequency,
+/// recording it for [`detected_cntfrq`], and return the authoritative value,
+/// see [`authoritative_counter_hz`].
+pub fn check_cntfrq() -> u64 {
+    let detected = read_cntfrq();
+    DETECTED_CNTFRQ.store(detected, Ordering::Relaxed);
+    authoritative_counter_hz(detected, nanos_to_ticks(NANOS_PER_SEC))
+}
+
+/// Get the `CNTFRQ_EL0` value seen by the last [`check_cntfrq`], 0 if none
+/// ran yet.
+pub fn detected_cntfrq() -> u64 {
+    DETECTED_CNTFRQ.load(Ordering::Relaxed)
+}
+
+/// CPUs (below 64) that ran [`enable_cntvct_access_on`], one bit each.
+static CNTVCT_ENABLED_CPUS: AtomicU64 = AtomicU64::new(0);
+
+/// Open the counter to EL0 on the calling CPU, which is `cpu_id`, and record
+/// it for [`cntvct_enabled_on`].
+pub fn enable_cntvct_access_on(cpu_id: usize) {
+    enable_cntvct_access();
+    if cpu_id < 64 {
+        CNTVCT_ENABLED_CPUS.fetch_or(1 << cpu_id, Ordering::Relaxed);
+    }
+}
+
+/// Whether [`enable_cntvct_access_on`] ran on `cpu_id`. Always `false` for
+/// CPUs numbered 64 and above, which are not tracked.
+pub fn cntvct_enabled_on(cpu_id: usize) -> bool {
+    cpu_id < 64 && CNTVCT_ENABLED_CPUS.load(Ordering::Relaxed) & (1 << cpu_id) != 0
+}
+
+/// Open the counter selected by [`set_aarch64_counter`] to EL0 via
+/// `CNTKCTL_EL1`.
+///
+/// `CNTKCTL_EL1` is per CPU, so this only affects the calling CPU. Called
+/// for the boot CPU by `init_vdso_data` with the default `auto-enable-cntvct`
+/// feature and for the others by `on_cpu_online`. Without the feature, call
+/// this manually on every CPU (or configure `CNTKCTL_EL1` otherwise) before
+/// userspace runs there.
 pub fn enable_cntvct_access() {
     log::info!("Enabling user-space access to timer counter registers...");
     unsafe {
         let mut cntkctl_el1: u64;
         core::arch::asm!("mrs {}, CNTKCTL_EL1", out(reg) cntkctl_el1);
 
-        cntkctl_el1 |= 0x3;
+        cntkctl_el1 |= match aarch64_counter() {
+            Aarch64Counter::Physical => CNTKCTL_EL0PCTEN,
+            Aarch64Counter::Virtual => CNTKCTL_EL0VCTEN,
+        };
 
         core::arch::asm!("msr CNTKCTL_EL1, {}", in(reg) cntkctl_el1);
         core::arch::asm!("isb");
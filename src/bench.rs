@@ -0,0 +1,64 @@
+//! Cost of the vDSO update path, built with the `bench` feature.
+//!
+//! Measures a full update against the `mult`/`shift` recomputation inside it,
+//! the 128-bit division loop of `clocks_calc_mult_shift`, which is the work a
+//! quick update reusing the previous scaling would skip. Expect that share to
+//! dominate on cores without a fast 128-bit divide (riscv64, loongarch64) and
+//! to matter less on x86_64, where the platform time reads weigh more.
+use core::hint::black_box;
+
+use axplat::time::NANOS_PER_SEC;
+
+use crate::{vdso::update_vdso_data, vdso_data::read_arch_counter, vdso_time_data};
+
+/// Average cost of the measured operations, in hardware counter ticks.
+#[derive(Debug, Clone, Copy)]
+pub struct UpdateCost {
+    /// A full [`update_vdso_data`], as published by the timer tick.
+    pub full_update: u64,
+    /// The `mult`/`shift` recomputation alone.
+    pub mult_shift: u64,
+}
+
+impl UpdateCost {
+    /// Speedup a quick update skipping the recomputation would achieve, in
+    /// hundredths (e.g. 150 for 1.5x).
+    pub fn quick_update_speedup(&self) -> u64 {
+        let quick = self.full_update.saturating_sub(self.mult_shift).max(1);
+        self.full_update * 100 / quick
+    }
+}
+
+/// Time `f` over `iterations` runs and return the average in counter ticks.
+fn average_ticks(iterations: u64, mut f: impl FnMut()) -> u64 {
+    let start = read_arch_counter();
+    for _ in 0..iterations {
+        f();
+    }
+    read_arch_counter().wrapping_sub(start) / iterations.max(1)
+}
+
+/// Measure [`UpdateCost`] over `iterations` runs each and log the result.
+///
+/// Publishes `iterations` real updates, so run it on an idle system.
+pub fn measure_update_cost(iterations: u64) -> UpdateCost {
+    let ticks_per_sec = vdso_time_data::counter_hz();
+    let cost = UpdateCost {
+        full_update: average_ticks(iterations, update_vdso_data),
+        mult_shift: average_ticks(iterations, || {
+            black_box(vdso_time_data::clocks_calc_mult_shift(
+                black_box(ticks_per_sec),
+                NANOS_PER_SEC,
+                10,
+            ));
+        }),
+    };
+    log::info!(
+        "vDSO update: {} ticks, mult/shift: {} ticks, quick update speedup {}.{:02}x",
+        cost.full_update,
+        cost.mult_shift,
+        cost.quick_update_speedup() / 100,
+        cost.quick_update_speedup() % 100
+    );
+    cost
+}
@@ -0,0 +1,276 @@
+//! Inspection of the embedded vDSO ELF image.
+use axerrno::{AxError, AxResult};
+use xmas_elf::{ElfFile, program::Type, sections::SectionData, symbol_table::Entry};
+
+/// Check that the vDSO image is present at all: non-empty and starting with
+/// the ELF magic.
+///
+/// An empty image comes from a zero-byte `vdso/vdso_<arch>.so` placeholder;
+/// mapping it would make userspace crash on its first vDSO call instead.
+pub fn check_image(elf_bytes: &[u8]) -> AxResult<()> {
+    if elf_bytes.is_empty() {
+        log::error!("embedded vDSO image is empty, rebuild vdso/vdso_<arch>.so");
+        return Err(AxError::InvalidExecutable);
+    }
+    if !elf_bytes.starts_with(b"\x7fELF") {
+        log::error!("embedded vDSO image is not an ELF file");
+        return Err(AxError::InvalidExecutable);
+    }
+    if let Some((major, minor, patch)) = vdso_version(elf_bytes) {
+        log::debug!("vDSO image built for Linux {}.{}.{}", major, minor, patch);
+    }
+    Ok(())
+}
+
+/// Check that every loadable segment of the vDSO image can be mapped with
+/// pages of `page_size` bytes.
+///
+/// A segment whose file offset and virtual address disagree modulo the page
+/// size cannot be mapped page by page, which is what happens when a vDSO
+/// linked for larger pages is used on a kernel with smaller ones.
+///
+/// `p_align` itself is not required to equal `page_size`: linkers record
+/// the max page size they were told about (the bundled aarch64 image has
+/// 0x8, the loongarch64 one 0x4000), and a congruent segment maps fine
+/// either way. A `p_align` above `page_size` is only logged.
+pub fn check_load_alignment(elf_bytes: &[u8], page_size: usize) -> AxResult<()> {
+    let elf = ElfFile::new(elf_bytes).map_err(|_| AxError::InvalidExecutable)?;
+    let page_mask = page_size as u64 - 1;
+    for ph in elf.program_iter() {
+        if ph.get_type() != Ok(Type::Load) {
+            continue;
+        }
+        if (ph.offset() ^ ph.virtual_addr()) & page_mask != 0 {
+            log::error!(
+                "vDSO PT_LOAD at {:#x} (offset {:#x}) is not mappable with {:#x}-byte pages",
+                ph.virtual_addr(),
+                ph.offset(),
+                page_size
+            );
+            return Err(AxError::InvalidExecutable);
+        }
+        if ph.align() > page_size as u64 {
+            log::warn!(
+                "vDSO PT_LOAD is aligned to {:#x} but kernel pages are {:#x}",
+                ph.align(),
+                page_size
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Compute the load bias of the vDSO image when mapped at `user_base`: the
+/// value added to link-time addresses to get user addresses.
+///
+/// The bias is taken relative to the `p_vaddr` of the first `PT_LOAD`
+/// segment, which is placed at `user_base`. An image without a loadable
+/// segment is treated as linked at address 0.
+pub fn vdso_load_bias(user_base: usize, elf_bytes: &[u8]) -> usize {
+    let first_load_vaddr = ElfFile::new(elf_bytes)
+        .ok()
+        .and_then(|elf| {
+            elf.program_iter()
+                .find(|ph| ph.get_type() == Ok(Type::Load))
+                .map(|ph| ph.virtual_addr() as usize)
+        })
+        .unwrap_or(0);
+    user_base.wrapping_sub(first_load_vaddr)
+}
+
+/// Find the link-time address of the dynamic symbol `name` in the vDSO
+/// image, e.g. `__vdso_clock_gettime`. Undefined symbols are skipped.
+pub fn vdso_symbol_vaddr(elf_bytes: &[u8], name: &str) -> Option<usize> {
+    let elf = ElfFile::new(elf_bytes).ok()?;
+    let dynsym = elf.find_section_by_name(".dynsym")?;
+    let SectionData::DynSymbolTable64(entries) = dynsym.get_data(&elf).ok()? else {
+        return None;
+    };
+    entries
+        .iter()
+        .filter(|sym| sym.shndx() != 0)
+        .find(|sym| sym.get_name(&elf) == Ok(name))
+        .map(|sym| sym.value() as usize)
+}
+
+/// Compute the user address of the dynamic symbol `name` once the vDSO
+/// image's ELF header is mapped at `vdso_user_base` (the `AT_SYSINFO_EHDR`
+/// value), for fast-syscall tables or debuggers.
+pub fn vdso_symbol_user_addr(name: &str, vdso_user_base: usize, elf_bytes: &[u8]) -> Option<usize> {
+    let vaddr = vdso_symbol_vaddr(elf_bytes, name)?;
+    Some(vdso_load_bias(vdso_user_base, elf_bytes).wrapping_add(vaddr))
+}
+
+/// Owner name of StarryOS-specific notes in the vDSO image.
+pub const STARRY_NOTE_NAME: &[u8] = b"StarryOS";
+/// Note type declaring the clock modes the vDSO can read. The descriptor is a
+/// native-endian `u32` with bit `n` set when `clock_mode == n` is supported.
+///
+/// The userspace build emits it from any assembly or C source linked into the
+/// vDSO, e.g. for a vDSO that only understands `ClockMode::None` and mode 1:
+///
+/// ```text
+/// .pushsection .note.starry, "a", @note
+/// .long 9, 4, 1          // namesz, descsz, NT_STARRY_CLOCK_MODES
+/// .asciz "StarryOS"
+/// .balign 4
+/// .long 0x3              // supported clock mode bitmask
+/// .balign 4
+/// .popsection
+/// ```
+///
+/// and keeps `.note.*` inside the `PT_NOTE` segment of its linker script.
+pub const NT_STARRY_CLOCK_MODES: u32 = 1;
+
+/// Return the supported clock mode bitmask declared by the vDSO image, or
+/// `None` if it carries no [`NT_STARRY_CLOCK_MODES`] note.
+pub fn supported_clock_modes(elf_bytes: &[u8]) -> Option<u32> {
+    let desc = find_note(elf_bytes, STARRY_NOTE_NAME, NT_STARRY_CLOCK_MODES)?;
+    Some(u32::from_ne_bytes(desc.try_into().ok()?))
+}
+
+/// Owner name of the note carrying the kernel version the vDSO was built
+/// for (`LINUX_VERSION_CODE`).
+const LINUX_NOTE_NAME: &[u8] = b"Linux";
+/// Type of the version note, as emitted by the kernel's `ELFNOTE32`.
+const NT_LINUX_VERSION: u32 = 0;
+
+/// Get the kernel version `(major, minor, patch)` from the vDSO image's
+/// `Linux` version note, or `None` if it has none.
+pub fn vdso_version(elf_bytes: &[u8]) -> Option<(u32, u32, u32)> {
+    let desc = find_note(elf_bytes, LINUX_NOTE_NAME, NT_LINUX_VERSION)?;
+    let code = u32::from_ne_bytes(desc.try_into().ok()?);
+    Some((code >> 16, (code >> 8) & 0xff, code & 0xff))
+}
+
+/// Find the descriptor of the note with the given owner `name` and `n_type`
+/// in the `PT_NOTE` segments of the image.
+fn find_note<'a>(elf_bytes: &'a [u8], name: &[u8], n_type: u32) -> Option<&'a [u8]> {
+    let elf = ElfFile::new(elf_bytes).ok()?;
+    elf.program_iter()
+        .filter(|ph| ph.get_type() == Ok(Type::Note))
+        .filter_map(|ph| {
+            let start = ph.offset() as usize;
+            let end = start.checked_add(ph.file_size() as usize)?;
+            elf_bytes.get(start..end)
+        })
+        .find_map(|notes| find_note_in(notes, name, n_type))
+}
+
+/// Walk the notes of one `PT_NOTE` segment looking for `name` and `n_type`.
+fn find_note_in<'a>(mut notes: &'a [u8], name: &[u8], n_type: u32) -> Option<&'a [u8]> {
+    let word = |i: usize, b: &[u8]| -> Option<u32> {
+        Some(u32::from_ne_bytes(
+            b.get(i * 4..i * 4 + 4)?.try_into().ok()?,
+        ))
+    };
+    while notes.len() >= 12 {
+        let namesz = word(0, notes)? as usize;
+        let descsz = word(1, notes)? as usize;
+        let note_type = word(2, notes)?;
+        let desc_start = 12 + namesz.next_multiple_of(4);
+        let note_name = notes.get(12..12 + namesz)?;
+        let desc = notes.get(desc_start..desc_start + descsz)?;
+        if note_type == n_type && note_name.strip_suffix(b"\0") == Some(name) {
+            return Some(desc);
+        }
+        notes = notes.get(desc_start + descsz.next_multiple_of(4)..)?;
+    }
+    None
+}
+
+/// Check that the vDSO image can read clocks published in `clock_mode`.
+///
+/// Fails with `InvalidExecutable` if the image declares its supported modes
+/// and `clock_mode` is not among them, since userspace would misread every
+/// clock. Images without the declaration are accepted as before.
+pub fn check_clock_mode(elf_bytes: &[u8], clock_mode: i32) -> AxResult<()> {
+    let Some(modes) = supported_clock_modes(elf_bytes) else {
+        log::debug!("vDSO image does not declare its supported clock modes");
+        return Ok(());
+    };
+    let supported = (0..32).contains(&clock_mode) && modes & (1 << clock_mode) != 0;
+    if !supported {
+        log::error!(
+            "vDSO image supports clock modes {:#x} but the kernel publishes mode {}",
+            modes,
+            clock_mode
+        );
+        return Err(AxError::InvalidExecutable);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 64-bit little-endian ELF header followed by one `PT_LOAD` per
+    /// `(p_offset, p_vaddr, p_align)`, in an 8-byte aligned buffer.
+    fn elf_with_loads(loads: &[(u64, u64, u64)]) -> Vec<u64> {
+        let mut bytes = vec![0u8; 64 + 56 * loads.len()];
+        bytes[..8].copy_from_slice(b"\x7fELF\x02\x01\x01\x00");
+        bytes[16..18].copy_from_slice(&3u16.to_le_bytes()); // ET_DYN
+        bytes[18..20].copy_from_slice(&62u16.to_le_bytes());
+        bytes[20..24].copy_from_slice(&1u32.to_le_bytes());
+        bytes[32..40].copy_from_slice(&64u64.to_le_bytes()); // e_phoff
+        bytes[52..54].copy_from_slice(&64u16.to_le_bytes());
+        bytes[54..56].copy_from_slice(&56u16.to_le_bytes());
+        bytes[56..58].copy_from_slice(&(loads.len() as u16).to_le_bytes());
+        bytes[58..60].copy_from_slice(&64u16.to_le_bytes());
+        for (i, &(offset, vaddr, align)) in loads.iter().enumerate() {
+            let ph = &mut bytes[64 + 56 * i..][..56];
+            ph[..4].copy_from_slice(&1u32.to_le_bytes()); // PT_LOAD
+            ph[8..16].copy_from_slice(&offset.to_le_bytes());
+            ph[16..24].copy_from_slice(&vaddr.to_le_bytes());
+            ph[24..32].copy_from_slice(&vaddr.to_le_bytes());
+            ph[32..40].copy_from_slice(&0x100u64.to_le_bytes());
+            ph[40..48].copy_from_slice(&0x100u64.to_le_bytes());
+            ph[48..56].copy_from_slice(&align.to_le_bytes());
+        }
+        bytes
+            .chunks(8)
+            .map(|word| u64::from_le_bytes(word.try_into().unwrap()))
+            .collect()
+    }
+
+    fn as_bytes(words: &[u64]) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(words.as_ptr() as *const u8, words.len() * 8) }
+    }
+
+    #[test]
+    fn load_alignment_requires_congruent_segments() {
+        const PAGE: usize = 0x1000;
+        // Congruent segments map whatever their recorded alignment.
+        for align in [0x8, 0x1000, 0x4000, 0x10000] {
+            let elf = elf_with_loads(&[(0, 0, align), (0x1234, 0x5234, align)]);
+            assert_eq!(
+                check_load_alignment(as_bytes(&elf), PAGE),
+                Ok(()),
+                "{align:#x}"
+            );
+        }
+        let elf = elf_with_loads(&[(0, 0, 0x1000), (0x1800, 0x2000, 0x1000)]);
+        assert_eq!(
+            check_load_alignment(as_bytes(&elf), PAGE),
+            Err(AxError::InvalidExecutable)
+        );
+        // Congruent at 4 KiB but not at 16 KiB.
+        let elf = elf_with_loads(&[(0x1000, 0x2000, 0x1000)]);
+        assert_eq!(check_load_alignment(as_bytes(&elf), PAGE), Ok(()));
+        assert_eq!(
+            check_load_alignment(as_bytes(&elf), 0x4000),
+            Err(AxError::InvalidExecutable)
+        );
+        assert_eq!(
+            check_load_alignment(&[0; 64], PAGE),
+            Err(AxError::InvalidExecutable)
+        );
+    }
+
+    #[test]
+    fn bundled_image_maps_with_4k_pages() {
+        let image = crate::embed::embedded_vdso();
+        assert_eq!(check_load_alignment(image, 0x1000), Ok(()));
+    }
+}
@@ -1,3 +1,10 @@
+//! Embedding of the prebuilt userspace vDSO image.
+//!
+//! The image is `vdso_<arch>.so` (`vdso_x86_64.so`, `vdso_aarch64.so`,
+//! `vdso_riscv64.so` or `vdso_loongarch64.so`) from the crate's `vdso`
+//! directory. Set `STARRY_VDSO_PATH` at build time to embed it from another
+//! directory instead; a relative path is taken relative to this crate.
+
 use core::arch::global_asm;
 
 macro_rules! include_vdso {
@@ -8,8 +15,8 @@ macro_rules! include_vdso {
             ".balign 4096\n",
             "vdso_start:\n",
             ".incbin \"",
-            env!("CARGO_MANIFEST_DIR"),
-            "/vdso/vdso_",
+            env!("STARRY_VDSO_DIR"),
+            "/vdso_",
             $arch,
             ".so\"\n",
             ".balign 4096\n",
@@ -30,3 +37,15 @@ cfg_if::cfg_if! {
         global_asm!(include_vdso!("loongarch64"));
     }
 }
+
+unsafe extern "C" {
+    static vdso_start: u8;
+    static vdso_end: u8;
+}
+
+/// Get the embedded vDSO image.
+pub fn embedded_vdso() -> &'static [u8] {
+    let start = &raw const vdso_start;
+    let len = &raw const vdso_end as usize - start as usize;
+    unsafe { core::slice::from_raw_parts(start, len) }
+}
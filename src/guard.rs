@@ -1,17 +1,24 @@
 extern crate alloc;
 
 use alloc::alloc::dealloc;
-use core::alloc::Layout;
+use core::{
+    alloc::Layout,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use kernel_guard::NoPreemptIrqSave;
 
 const PAGE_SIZE_4K: usize = 4096;
 
 /// RAII guard that will free allocated vdso pages on Drop unless disarmed.
 pub struct VdsoAllocGuard {
-    alloc: Option<(usize, usize)>,
+    alloc: Option<(usize, usize, usize)>,
 }
 
 impl VdsoAllocGuard {
-    pub fn new(alloc: Option<(usize, usize)>) -> Self {
+    /// Guard the `(alloc_vaddr, num_pages, align)` allocation returned in a
+    /// `VdsoPageInfo`.
+    pub fn new(alloc: Option<(usize, usize, usize)>) -> Self {
         Self { alloc }
     }
 
@@ -22,12 +29,64 @@ impl VdsoAllocGuard {
 
 impl Drop for VdsoAllocGuard {
     fn drop(&mut self) {
-        if let Some((vaddr, pages)) = self.alloc {
-            // free memory allocated with `alloc_zeroed` above
+        if let Some((vaddr, pages, align)) = self.alloc {
+            // free memory allocated with `alloc_zeroed` in
+            // `prepare_vdso_pages_aligned`, with the alignment it used
             let size = pages * PAGE_SIZE_4K;
-            if let Ok(layout) = Layout::from_size_align(size, PAGE_SIZE_4K) {
+            if let Ok(layout) = Layout::from_size_align(size, align) {
                 unsafe { dealloc(vaddr as *mut u8, layout) };
             }
         }
     }
 }
+
+/// Set while a CPU is writing the vDSO data page.
+static VDSO_WRITER_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// RAII guard marking the single writer of the vDSO data page.
+///
+/// The seqlock protocol in `VdsoClock` only supports one writer: two CPUs
+/// interleaving `write_seqcount_begin`/`write_seqcount_end` can leave the
+/// sequence odd and make userspace readers spin forever.
+///
+/// Interrupts and preemption stay disabled while the guard is held, like a
+/// `SpinNoIrq`: the timer interrupt takes the same lock in
+/// `update_vdso_data`, and would otherwise spin forever on a CPU that was
+/// interrupted while holding it from thread context.
+pub struct VdsoWriteGuard {
+    _irq: NoPreemptIrqSave,
+}
+
+impl VdsoWriteGuard {
+    /// Become the vDSO data writer, spinning until any other writer is done.
+    pub fn acquire() -> Self {
+        let irq = NoPreemptIrqSave::new();
+        while VDSO_WRITER_ACTIVE
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            while VDSO_WRITER_ACTIVE.load(Ordering::Relaxed) {
+                core::hint::spin_loop();
+            }
+        }
+        Self { _irq: irq }
+    }
+
+    /// Try to become the vDSO data writer, returning `None` if another CPU
+    /// is already updating.
+    pub fn try_acquire() -> Option<Self> {
+        let irq = NoPreemptIrqSave::new();
+        VDSO_WRITER_ACTIVE
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| Self { _irq: irq })
+    }
+}
+
+impl Drop for VdsoWriteGuard {
+    fn drop(&mut self) {
+        // Runs before `_irq` is dropped, so the lock is released with
+        // interrupts still disabled.
+        VDSO_WRITER_ACTIVE.store(false, Ordering::Release);
+    }
+}
@@ -1,4 +1,7 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
+#[cfg(feature = "bench")]
+pub mod bench;
+pub mod elf;
 pub mod embed;
 pub mod guard;
 pub mod vdso;
@@ -19,3 +22,11 @@ cfg_if::cfg_if! {
         pub use self::loongarch64::*;
     }
 }
+
+/// Serializes the tests that touch `VDSO_DATA` or the update's statics, which
+/// the test harness would otherwise run concurrently.
+#[cfg(test)]
+fn lock_globals() -> std::sync::MutexGuard<'static, ()> {
+    static GLOBALS: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    GLOBALS.lock().unwrap_or_else(|e| e.into_inner())
+}
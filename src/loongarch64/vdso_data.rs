@@ -1,10 +1,19 @@
-use crate::vdso_time_data::VdsoTimeData;
+use crate::{
+    config::{ClockMode, VVAR_PAGES},
+    vdso::VvarLayout,
+    vdso_time_data::{VdsoClock, VdsoTimeData},
+};
 
 #[repr(C)]
 pub struct VdsoData {
     pub time_data: VdsoTimeData,
 }
 
+// The data page is mapped to userspace, so it must start on a page boundary.
+const _: () = assert!(core::mem::align_of::<VdsoData>() >= 4096);
+// `VdsoClock` layout read by the bundled vDSO: no `max_cycles` field.
+const _: () = assert!(core::mem::size_of::<VdsoClock>() == 232);
+
 impl Default for VdsoData {
     fn default() -> Self {
         Self::new()
@@ -12,9 +21,21 @@ impl Default for VdsoData {
 }
 
 impl VdsoData {
+    /// Only the time page is backed; the timens, rng and arch pages that
+    /// follow it in the Linux order are reserved but not backed.
+    pub const VVAR_LAYOUT: VvarLayout = VvarLayout {
+        total_pages: VVAR_PAGES,
+        backed_pages: core::mem::size_of::<Self>() / 4096,
+        time_data: core::mem::offset_of!(Self, time_data) / 4096,
+        timens: 1,
+        rng: 2,
+        arch: 3,
+        pvclock: None,
+    };
+
     pub const fn new() -> Self {
         Self {
-            time_data: VdsoTimeData::new(),
+            time_data: VdsoTimeData::new().with_clock_mode(ClockMode::Csr as i32),
         }
     }
 
@@ -22,3 +43,24 @@ impl VdsoData {
         self.time_data.update();
     }
 }
+
+/// Read the LoongArch stable counter, the source the vDSO reads with
+/// `rdtime.d`. It runs at the platform's constant stable-counter frequency.
+pub fn read_stable_counter() -> u64 {
+    let counter: u64;
+    unsafe {
+        core::arch::asm!("rdtime.d {}, $zero", out(reg) counter, options(nomem, nostack));
+    }
+    counter
+}
+
+/// Read the counter the vDSO interpolates with.
+pub fn read_arch_counter() -> u64 {
+    read_stable_counter()
+}
+
+/// Read the counter as the vDSO's `__arch_get_hw_counter` does, which is a
+/// plain `rdtime.d` with no fence around it.
+pub fn read_hw_counter_fenced() -> u64 {
+    read_stable_counter()
+}
@@ -1,7 +1,34 @@
 pub const VVAR_PAGES: usize = 4;
 
+/// Number of clock bases in `VdsoClock::time_data` (`CLOCK_TAI + 1` in the
+/// kernel headers the bundled vDSO was built against).
+pub const VDSO_BASES: usize = 12;
+
+/// Valid bits of the hardware counter read by the vDSO.
+///
+/// The `time` CSR is 64 bits wide on RV64 (Linux `CLOCKSOURCE_MASK(64)` in
+/// `drivers/clocksource/timer-riscv.c`).
+pub const COUNTER_MASK: u64 = u64::MAX;
+
 #[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ClockMode {
     None,
     Csr,
 }
+
+impl ClockMode {
+    /// Convert a raw `clock_mode` value back to a known mode.
+    pub const fn from_raw(mode: i32) -> Option<Self> {
+        match mode {
+            0 => Some(Self::None),
+            1 => Some(Self::Csr),
+            _ => None,
+        }
+    }
+}
+
+// Values shared with the userspace vDSO (`VDSO_CLOCKMODE_NONE` and
+// `VDSO_CLOCKMODE_ARCHTIMER`).
+const _: () = assert!(ClockMode::None as i32 == 0);
+const _: () = assert!(ClockMode::Csr as i32 == 1);
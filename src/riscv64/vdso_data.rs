@@ -1,10 +1,29 @@
-use crate::vdso_time_data::VdsoTimeData;
+use crate::{
+    config::VVAR_PAGES,
+    vdso::VvarLayout,
+    vdso_time_data::{VdsoClock, VdsoTimeData},
+};
 
+/// The VVAR pages in the order of the Linux generic vDSO data store: time,
+/// time namespace, getrandom state and the arch page holding the `hwprobe`
+/// cache. All but the time page stay zeroed, which userspace treats as no
+/// time namespace, a getrandom state that is not ready (so it falls back to
+/// the syscall) and an empty `hwprobe` cache.
 #[repr(C)]
 pub struct VdsoData {
     pub time_data: VdsoTimeData,
+    pub timen_data: [u8; 4096],
+    pub rng_data: [u8; 4096],
+    pub arch_data: [u8; 4096],
 }
 
+// The data page is mapped to userspace, so it must start on a page boundary.
+const _: () = assert!(core::mem::align_of::<VdsoData>() >= 4096);
+// `VdsoClock` layout read by the bundled vDSO: no `max_cycles` field.
+const _: () = assert!(core::mem::size_of::<VdsoClock>() == 232);
+// Every mapped VVAR page is backed by this struct.
+const _: () = assert!(core::mem::size_of::<VdsoData>() == VVAR_PAGES * 4096);
+
 impl Default for VdsoData {
     fn default() -> Self {
         Self::new()
@@ -12,9 +31,22 @@ impl Default for VdsoData {
 }
 
 impl VdsoData {
+    pub const VVAR_LAYOUT: VvarLayout = VvarLayout {
+        total_pages: VVAR_PAGES,
+        backed_pages: core::mem::size_of::<Self>() / 4096,
+        time_data: core::mem::offset_of!(Self, time_data) / 4096,
+        timens: core::mem::offset_of!(Self, timen_data) / 4096,
+        rng: core::mem::offset_of!(Self, rng_data) / 4096,
+        arch: core::mem::offset_of!(Self, arch_data) / 4096,
+        pvclock: None,
+    };
+
     pub const fn new() -> Self {
         Self {
             time_data: VdsoTimeData::new(),
+            timen_data: [0u8; 4096],
+            rng_data: [0u8; 4096],
+            arch_data: [0u8; 4096],
         }
     }
 
@@ -22,3 +54,18 @@ impl VdsoData {
         self.time_data.update();
     }
 }
+
+/// Read the `time` CSR, the counter the vDSO interpolates with.
+pub fn read_arch_counter() -> u64 {
+    let counter: u64;
+    unsafe {
+        core::arch::asm!("rdtime {}", out(reg) counter, options(nomem, nostack));
+    }
+    counter
+}
+
+/// Read the counter as the vDSO's `__arch_get_hw_counter` does, which is a
+/// plain `rdtime` with no fence around it.
+pub fn read_hw_counter_fenced() -> u64 {
+    read_arch_counter()
+}
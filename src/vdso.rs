@@ -1,114 +1,1287 @@
 //! vDSO data management.
 extern crate alloc;
 extern crate log;
-use alloc::alloc::alloc_zeroed;
-use core::alloc::Layout;
+use alloc::alloc::{alloc_zeroed, dealloc};
+use core::{
+    alloc::Layout,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicU8, AtomicUsize, Ordering},
+};
 
 use axerrno::{AxError, AxResult};
-use axplat::{mem::virt_to_phys, time::monotonic_time_nanos};
+use axplat::{
+    mem::{PhysAddr, phys_to_virt, virt_to_phys},
+    time::{NANOS_PER_SEC, current_ticks, monotonic_time_nanos},
+};
+
+pub use crate::vdso_time_data::{
+    ClockBase, ClockSource, VDSO_MAX_CPUS, VdsoCpuInfo, VdsoTimestamp, authoritative_counter_hz,
+};
+use crate::{
+    config::{ClockMode, VVAR_PAGES},
+    guard::VdsoWriteGuard,
+    vdso_data::read_hw_counter_fenced,
+    vdso_time_data::VdsoTimeData,
+};
+
+#[cfg(not(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "riscv64",
+    target_arch = "loongarch64"
+)))]
+compile_error!("starry-vdso only supports x86_64, aarch64, riscv64 and loongarch64 targets");
 
 const PAGE_SIZE_4K: usize = 4096;
 
+/// Size in bytes of the VVAR data backed by [`VDSO_DATA`].
+pub const VVAR_BYTES: usize = core::mem::size_of::<crate::vdso_data::VdsoData>();
+
+/// Largest vDSO image [`prepare_vdso_pages`] accepts. Real images are a few
+/// pages; anything bigger points at corrupted `vdso_start`/`vdso_end` symbols.
+pub const MAX_VDSO_SIZE: usize = 1024 * 1024;
+
+/// Attempts a reader makes before concluding the vDSO writer is stuck.
+const MAX_READ_RETRIES: u32 = 1000;
+
+/// Set when the timekeeper has advanced since the last published update.
+static VDSO_TIME_DIRTY: AtomicBool = AtomicBool::new(false);
+
 /// Global vDSO data instance
 #[unsafe(link_section = ".data")]
 pub static mut VDSO_DATA: crate::vdso_data::VdsoData = crate::vdso_data::VdsoData::new();
 
 /// Initialize vDSO data
-pub fn init_vdso_data() {
+///
+/// Also publishes the platform timer granularity as `hrtimer_res`, which is
+/// what `clock_getres` reports, keeping the 1 ns default if the platform
+/// reports no counter frequency.
+///
+/// Fails with `BadAddress` if the data page cannot be mapped to userspace.
+/// With the `panic-on-init-error` feature a failure panics instead, for
+/// fail-fast bring-up.
+pub fn init_vdso_data() -> AxResult<()> {
+    let result = try_init_vdso_data();
+    #[cfg(feature = "panic-on-init-error")]
+    if let Err(e) = result {
+        panic!("vDSO initialization failed: {:?}", e);
+    }
+    result
+}
+
+fn try_init_vdso_data() -> AxResult<()> {
+    let _writer = VdsoWriteGuard::acquire();
     unsafe {
         let data_ptr = core::ptr::addr_of_mut!(VDSO_DATA);
+        #[cfg(target_arch = "aarch64")]
+        crate::vdso_time_data::set_counter_hz(crate::vdso_data::check_cntfrq());
+        if let Some(res) = platform_timer_resolution_ns() {
+            (*data_ptr).time_data.hrtimer_res = res;
+        }
         (*data_ptr).time_update();
+        if let Err(e) = checked_virt_to_phys(data_ptr as usize) {
+            log::error!("vDSO data page cannot be mapped to userspace: {:?}", e);
+            return Err(e);
+        }
         log::info!("vDSO data initialized at {:#x}", data_ptr as usize);
-        #[cfg(target_arch = "aarch64")]
+        #[cfg(all(target_arch = "aarch64", feature = "auto-enable-cntvct"))]
         {
-            crate::vdso_data::enable_cntvct_access();
+            crate::vdso_data::enable_cntvct_access_on(0);
             log::info!("vDSO CNTVCT access enabled");
         }
+        #[cfg(all(target_arch = "aarch64", not(feature = "auto-enable-cntvct")))]
+        log::info!("vDSO counter access not enabled: caller must set up CNTKCTL_EL1");
+    }
+    Ok(())
+}
+
+/// Per-CPU vDSO setup for a secondary CPU coming online, to be called on
+/// that CPU. [`init_vdso_data`] covers the boot CPU, taken to be CPU 0.
+///
+/// On aarch64 this checks the CPU's `CNTFRQ_EL0` and, with the
+/// `auto-enable-cntvct` feature, opens its counter to EL0: `CNTKCTL_EL1` is
+/// per CPU, so a process scheduled onto a CPU that skipped this faults on its
+/// first vDSO call.
+pub fn on_cpu_online(cpu_id: usize) {
+    #[cfg(target_arch = "aarch64")]
+    {
+        crate::vdso_data::check_cntfrq();
+        #[cfg(feature = "auto-enable-cntvct")]
+        crate::vdso_data::enable_cntvct_access_on(cpu_id);
+    }
+    log::debug!("vDSO set up on CPU {}", cpu_id);
+}
+
+/// Publish the CPU and NUMA node ids of `cpu` for a `getcpu` fast path, to
+/// be called as each CPU comes online. Returns `InvalidInput` for CPUs
+/// beyond `VDSO_MAX_CPUS`. See [`VdsoTimeData::cpu_info`] for the layout.
+pub fn set_cpu_info(cpu: u32, node: u32) -> AxResult<()> {
+    let _writer = VdsoWriteGuard::acquire();
+    unsafe {
+        (*core::ptr::addr_of_mut!(VDSO_DATA))
+            .time_data
+            .set_cpu_info(cpu, node)
+    }
+}
+
+/// Get the `(cpu, node)` ids published for `cpu`, or `None` if it has not
+/// come online.
+pub fn cpu_info(cpu: u32) -> Option<(u32, u32)> {
+    let entry = vdso_time_data().cpu_info.get(cpu as usize)?;
+    let info = unsafe { core::ptr::read_volatile(entry) };
+    (info != VdsoCpuInfo::UNSET).then_some((info.cpu, info.node))
+}
+
+/// Reinitialize vDSO data after the hardware counter was reset.
+///
+/// Must be called after any event that restarts the counter (kexec, soft
+/// reboot, firmware resume) since the old `cycle_last` would otherwise yield a
+/// huge delta. Clears `cycle_last` so the next update recomputes the scaling
+/// from scratch, then reruns [`init_vdso_data`] including the arch enables,
+/// returning its result.
+pub fn reinit_vdso_data() -> AxResult<()> {
+    {
+        let _writer = VdsoWriteGuard::acquire();
+        unsafe {
+            (*core::ptr::addr_of_mut!(VDSO_DATA))
+                .time_data
+                .reset_cycle_last()
+        };
+    }
+    init_vdso_data()
+}
+
+/// Republish vDSO time after resuming from a suspend of `suspend_ns`.
+///
+/// Boottime advances by `suspend_ns`, monotonic stays continuous with what the
+/// platform reports, and realtime is re-synced to the wall clock without
+/// smoothing. `cycle_last` is cleared so the first post-resume update does not
+/// interpolate across the suspend.
+pub fn on_resume(suspend_ns: u64) {
+    let _writer = VdsoWriteGuard::acquire();
+    crate::vdso_time_data::account_suspend(suspend_ns);
+    unsafe {
+        let data_ptr = core::ptr::addr_of_mut!(VDSO_DATA);
+        (*data_ptr).time_data.reset_cycle_last();
+        (*data_ptr).time_update();
     }
 }
 
 /// Update vDSO data
+///
+/// May be called from any CPU, including from the timer interrupt: concurrent
+/// callers are serialized by a writer lock held across the whole seqlock
+/// write section, with interrupts disabled so a thread-context writer cannot
+/// be interrupted by an update on its own CPU.
 pub fn update_vdso_data() {
+    {
+        let _writer = VdsoWriteGuard::acquire();
+        unsafe {
+            let data_ptr = core::ptr::addr_of_mut!(VDSO_DATA);
+            (*data_ptr).time_update();
+        }
+    }
+    run_update_callback();
+}
+
+/// State published by an update, passed to the [`set_update_callback`]
+/// callback.
+#[derive(Debug, Clone, Copy)]
+pub struct UpdateResult {
+    /// Monotonic time of the update, in nanoseconds.
+    pub mono_ns: u64,
+    /// Counter value the published bases correspond to.
+    pub cycle_last: u64,
+    /// Published counter-to-nanosecond multiplier.
+    pub mult: u32,
+    /// Published counter-to-nanosecond shift.
+    pub shift: u32,
+}
+
+/// Callback registered with [`set_update_callback`], null if none.
+static UPDATE_CALLBACK: AtomicPtr<()> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Register `callback` to run after every [`update_vdso_data`], or remove
+/// the current one with `None`. Only one callback is kept.
+///
+/// The callback runs on the updating CPU after the writer lock is released,
+/// typically from the timer tick. It must be short and must not call
+/// [`update_vdso_data`], which would recurse. Updates skipped while the clock
+/// is frozen do not run it.
+pub fn set_update_callback(callback: Option<fn(&UpdateResult)>) {
+    let ptr = callback.map_or(core::ptr::null_mut(), |f| f as *mut ());
+    UPDATE_CALLBACK.store(ptr, Ordering::Release);
+}
+
+/// Run the registered update callback, if any.
+fn run_update_callback() {
+    let ptr = UPDATE_CALLBACK.load(Ordering::Acquire);
+    if ptr.is_null() || crate::vdso_time_data::is_frozen() {
+        return;
+    }
+    let callback = unsafe { core::mem::transmute::<*mut (), fn(&UpdateResult)>(ptr) };
+    let data = vdso_time_data();
+    let result = data.timekeeper().read(|clk| UpdateResult {
+        mono_ns: data.last_update_mono_ns,
+        cycle_last: clk.cycle_last.load(Ordering::Relaxed),
+        mult: clk.mult,
+        shift: clk.shift,
+    });
+    callback(&result);
+}
+
+/// Switch the vDSO clocks to `mode`, e.g. once a counter comes online on a
+/// platform that booted in `ClockMode::None`, and publish an update that
+/// recomputes the scaling for it.
+pub fn set_clock_mode(mode: ClockMode) {
+    let _writer = VdsoWriteGuard::acquire();
     unsafe {
         let data_ptr = core::ptr::addr_of_mut!(VDSO_DATA);
+        (*data_ptr).time_data.set_clock_mode(mode);
         (*data_ptr).time_update();
     }
 }
 
+/// Like [`set_clock_mode`], but for the clock serving `source` only.
+pub fn set_clock_mode_of(source: ClockSource, mode: ClockMode) {
+    let _writer = VdsoWriteGuard::acquire();
+    unsafe {
+        let data_ptr = core::ptr::addr_of_mut!(VDSO_DATA);
+        (*data_ptr).time_data.set_clock_mode_of(source, mode);
+        (*data_ptr).time_update();
+    }
+}
+
+/// Freeze the vDSO clocks at their current time for record/replay debugging.
+///
+/// Userspace reads a constant time and [`update_vdso_data`] does nothing
+/// until [`unfreeze_vdso_clock`].
+pub fn freeze_vdso_clock() {
+    let _writer = VdsoWriteGuard::acquire();
+    unsafe {
+        (*core::ptr::addr_of_mut!(VDSO_DATA)).time_data.freeze();
+    }
+}
+
+/// Undo [`freeze_vdso_clock`] and publish the current time.
+pub fn unfreeze_vdso_clock() {
+    let _writer = VdsoWriteGuard::acquire();
+    unsafe {
+        (*core::ptr::addr_of_mut!(VDSO_DATA)).time_data.unfreeze();
+    }
+}
+
+/// Change the counter interval, in seconds, `mult`/`shift` are sized for and
+/// publish an update with the recomputed scaling (default 10, at least 1).
+///
+/// A smaller value gives a more precise conversion but requires updates at
+/// least that often, or userspace's `delta * mult` overflows.
+pub fn set_max_conversion_secs(secs: u32) {
+    crate::vdso_time_data::set_max_conversion_secs(secs);
+    update_vdso_data();
+}
+
+/// Derive the vDSO scaling from a counter frequency of `hz` instead of the
+/// one the platform reports, or go back to the platform's with 0, and
+/// publish an update with the new scaling.
+///
+/// On aarch64 [`init_vdso_data`] sets this to the result of
+/// [`authoritative_counter_hz`]. See [`counter_frequency_hz`].
+pub fn set_counter_frequency_hz(hz: u64) {
+    crate::vdso_time_data::set_counter_hz(hz);
+    update_vdso_data();
+}
+
+/// Force identity scaling for a counter that already counts nanoseconds,
+/// or stop forcing it, and publish an update with the new scaling. See
+/// `vdso_time_data::set_identity_scaling`.
+pub fn set_identity_scaling(enabled: bool) {
+    crate::vdso_time_data::set_identity_scaling(enabled);
+    update_vdso_data();
+}
+
+/// Mark the published vDSO time as out of date.
+///
+/// The timekeeping subsystem calls this when wall or monotonic time advances
+/// meaningfully (e.g. after a clock step or frequency change) so that the next
+/// [`update_if_dirty`] republishes it.
+pub fn mark_time_dirty() {
+    VDSO_TIME_DIRTY.store(true, Ordering::Release);
+}
+
+/// Update vDSO data only if [`mark_time_dirty`] was called since the last
+/// update, returning whether an update was performed.
+///
+/// Between updates userspace keeps interpolating from the last published base
+/// with the hardware counter, so skipping an update only loses precision when
+/// the counter scaling has drifted from the timekeeper. Tickless kernels can
+/// use this to coalesce periodic updates.
+pub fn update_if_dirty() -> bool {
+    if !VDSO_TIME_DIRTY.swap(false, Ordering::AcqRel) {
+        return false;
+    }
+    update_vdso_data();
+    true
+}
+
+/// Start or stop smearing a leap second into the published realtime.
+///
+/// While `active`, each update publishes realtime biased by `rate_ppb` parts
+/// per billion of the monotonic time elapsed since activation, leaving the
+/// published monotonic and boottime untouched. Realtime never goes back
+/// during a smear: a negative rate slows the timekeeper's interpolation down
+/// so realtime advances continuously at the smeared rate, while a positive
+/// rate steps realtime forward at each update. Stop the smear once the
+/// timekeeper has applied the leap second itself.
+///
+/// The data is republished at once, so the smeared rate takes effect from
+/// the start of the smear.
+pub fn set_leap_smear(active: bool, rate_ppb: i64) {
+    crate::vdso_time_data::set_leap_smear(active, rate_ppb);
+    update_vdso_data();
+}
+
+/// Get the published timezone as `(tz_minuteswest, tz_dsttime)`.
+///
+/// Read under the seqlock of the timekeeper clock, so the pair is consistent
+/// with itself and with the realtime base written by [`set_time_and_timezone`].
+/// Userspace reads the fields without the seqlock and has no such guarantee.
+pub fn vdso_timezone() -> (i32, i32) {
+    let data = vdso_time_data();
+    data.timekeeper().read(|_| data.timezone())
+}
+
+/// Set realtime and the timezone together, as `settimeofday` does with both
+/// arguments, in one seqlock window. See
+/// [`VdsoTimeData::set_time_and_timezone`].
+pub fn set_time_and_timezone(wall_ns: u64, tz_minuteswest: i32, tz_dsttime: i32) {
+    let _writer = VdsoWriteGuard::acquire();
+    unsafe {
+        (*core::ptr::addr_of_mut!(VDSO_DATA))
+            .time_data
+            .set_time_and_timezone(wall_ns, tz_minuteswest, tz_dsttime);
+    }
+}
+
+/// Shared view of the published time data.
+fn vdso_time_data() -> &'static VdsoTimeData {
+    unsafe { &(*core::ptr::addr_of!(VDSO_DATA)).time_data }
+}
+
+/// Raw `clock_mode` currently published to userspace.
+pub fn current_clock_mode() -> i32 {
+    vdso_time_data().timekeeper().read(|clk| clk.clock_mode)
+}
+
+/// Get the live `(mult, shift, cycle_last)` of the primary vDSO clock.
+///
+/// The values are read under the clock's seqlock, so they always belong to
+/// the same update. See [`counter_frequency_hz`] for the counter frequency
+/// they were derived from.
+pub fn clock_calibration() -> (u32, u32, u64) {
+    vdso_time_data()
+        .timekeeper()
+        .read(|clk| (clk.mult, clk.shift, clk.cycle_last.load(Ordering::Relaxed)))
+}
+
+/// [`vdso_capabilities`] bit: `clock_gettime` runs without a syscall.
+pub const VDSO_CAP_CLOCK_GETTIME: u64 = 1 << 0;
+/// [`vdso_capabilities`] bit: `gettimeofday` runs without a syscall.
+pub const VDSO_CAP_GETTIMEOFDAY: u64 = 1 << 1;
+/// [`vdso_capabilities`] bit: `time` runs without a syscall.
+pub const VDSO_CAP_TIME: u64 = 1 << 2;
+/// [`vdso_capabilities`] bit: `getrandom` runs without a syscall. Never set
+/// yet, see `RNG_DATA_PUBLISHED`.
+pub const VDSO_CAP_GETRANDOM: u64 = 1 << 3;
+/// [`vdso_capabilities`] bit: `getcpu` runs without a syscall. Never set yet,
+/// see `GETCPU_DATA_PUBLISHED`.
+pub const VDSO_CAP_GETCPU: u64 = 1 << 4;
+
+/// Whether the getrandom state page the vDSO's `getrandom` reads is
+/// published. This crate leaves it zeroed.
+const RNG_DATA_PUBLISHED: bool = false;
+
+/// Whether the per-CPU state the vDSO's `getcpu` reads is set up. This crate
+/// sets up none of it: the x86_64 image needs `TSC_AUX` or a per-CPU GDT
+/// entry, and the loongarch64 one reads the arch data page, which is not
+/// backed (see [`VvarLayout::backed_pages`]) and would fault.
+const GETCPU_DATA_PUBLISHED: bool = false;
+
+/// Prefix of the vDSO function symbols, e.g. `__vdso_clock_gettime`.
+#[cfg(target_arch = "aarch64")]
+const VDSO_SYMBOL_PREFIX: &str = "__kernel_";
+#[cfg(not(target_arch = "aarch64"))]
+const VDSO_SYMBOL_PREFIX: &str = "__vdso_";
+
+/// Get the `VDSO_CAP_*` bits of the functions the embedded vDSO image
+/// serves, for a loader to expose to userspace.
+///
+/// A bit is set when the image exports the function and the data it reads
+/// is published. `clock_gettime` and `gettimeofday` need a clock mode other
+/// than `ClockMode::None`, while `time` only reads the realtime seconds every
+/// update publishes. `getrandom` and `getcpu` need data this crate does not
+/// publish yet; otherwise the functions fall back to the syscall.
+pub fn vdso_capabilities() -> u64 {
+    vdso_capabilities_of(crate::embed::embedded_vdso())
+}
+
+/// Like [`vdso_capabilities`], for the vDSO image `elf_bytes`.
+pub fn vdso_capabilities_of(elf_bytes: &[u8]) -> u64 {
+    let has_counter = current_clock_mode() != ClockMode::None as i32;
+    let functions = [
+        (VDSO_CAP_CLOCK_GETTIME, "clock_gettime", has_counter),
+        (VDSO_CAP_GETTIMEOFDAY, "gettimeofday", has_counter),
+        (VDSO_CAP_TIME, "time", true),
+        (VDSO_CAP_GETRANDOM, "getrandom", RNG_DATA_PUBLISHED),
+        (VDSO_CAP_GETCPU, "getcpu", GETCPU_DATA_PUBLISHED),
+    ];
+    functions
+        .iter()
+        .filter(|(_, _, usable)| *usable)
+        .filter(|(_, name, _)| {
+            let symbol = alloc::format!("{VDSO_SYMBOL_PREFIX}{name}");
+            crate::elf::vdso_symbol_vaddr(elf_bytes, &symbol).is_some()
+        })
+        .fold(0, |caps, (bit, ..)| caps | bit)
+}
+
+/// Whether the vDSO data maintains a time base for `clock_id`, i.e. whether
+/// [`vdso_clock_gettime`] can serve it.
+pub fn clock_supported(clock_id: i32) -> bool {
+    ClockBase::from_clock_id(clock_id).is_some()
+}
+
+/// Emulate the userspace `clock_gettime` fast path for `clock_id`.
+///
+/// Reads the hardware counter with the vDSO's barriers (see
+/// `read_hw_counter_fenced`) and interpolates from the base published in the
+/// clock the vDSO reads it from, exactly as the vDSO does (see
+/// `VdsoClock::hres_time`). The returned `nsec` is in
+/// plain nanoseconds. Returns `None` for clocks the vDSO does not serve, or
+/// if a writer held the seqlock for more than `MAX_READ_RETRIES` attempts.
+pub fn vdso_clock_gettime(clock_id: i32) -> Option<VdsoTimestamp> {
+    let base = ClockBase::from_clock_id(clock_id)?;
+    vdso_time_data()
+        .clock(base.source())
+        .try_read(MAX_READ_RETRIES, |clk| {
+            clk.hres_time(base, read_hw_counter_fenced())
+        })
+        .ok()
+}
+
+/// Emulate the userspace `gettimeofday` fast path, returning
+/// `(tv_sec, tv_usec, tz_minuteswest, tz_dsttime)`.
+///
+/// The time and timezone come from one seqlock read of the timekeeper clock.
+/// Microseconds are truncated from nanoseconds, as `__vdso_gettimeofday`
+/// does. Returns `None` if a writer held the seqlock for more than
+/// `MAX_READ_RETRIES` attempts.
+pub fn vdso_gettimeofday() -> Option<(u64, u64, i32, i32)> {
+    let data = vdso_time_data();
+    let (ts, (tz_minuteswest, tz_dsttime)) = data
+        .timekeeper()
+        .try_read(MAX_READ_RETRIES, |clk| {
+            (
+                clk.hres_time(ClockBase::Realtime, read_hw_counter_fenced()),
+                data.timezone(),
+            )
+        })
+        .ok()?;
+    Some((ts.sec, ts.nsec / 1000, tz_minuteswest, tz_dsttime))
+}
+
+/// Read `(realtime, monotonic)` for the same instant, as plain nanoseconds.
+///
+/// Both are interpolated from one counter read inside one seqlock window, so
+/// their difference is exactly the published realtime-to-monotonic offset.
+/// Separate [`vdso_clock_gettime`] calls may straddle an update. Returns
+/// `None` if a writer held the seqlock for more than `MAX_READ_RETRIES`
+/// attempts.
+pub fn vdso_now_pair() -> Option<(VdsoTimestamp, VdsoTimestamp)> {
+    vdso_time_data()
+        .timekeeper()
+        .try_read(MAX_READ_RETRIES, |clk| {
+            let cycles = read_hw_counter_fenced();
+            (
+                clk.hres_time(ClockBase::Realtime, cycles),
+                clk.hres_time(ClockBase::Monotonic, cycles),
+            )
+        })
+        .ok()
+}
+
+/// Read every clock the vDSO serves, with its Linux name, for bring-up
+/// checks.
+///
+/// All values are interpolated from one counter read. The raw clock is read
+/// inside the timekeeper's seqlock window, so both are retried until neither
+/// was written during the read; the coarse clocks are the values published
+/// at the last update. For a sane timekeeper realtime is ahead of
+/// monotonic, boottime is not behind it, and the coarse clocks trail their
+/// fine counterparts by at most one update interval. Returns `None` if a
+/// writer held either seqlock for more than `MAX_READ_RETRIES` attempts.
+pub fn dump_all_clocks() -> Option<[(&'static str, VdsoTimestamp); 6]> {
+    let data = vdso_time_data();
+    data.timekeeper()
+        .try_read(MAX_READ_RETRIES, |tk| {
+            data.clock(ClockSource::Raw)
+                .try_read(MAX_READ_RETRIES, |raw| {
+                    let cycles = read_hw_counter_fenced();
+                    [
+                        ("CLOCK_REALTIME", tk.hres_time(ClockBase::Realtime, cycles)),
+                        (
+                            "CLOCK_MONOTONIC",
+                            tk.hres_time(ClockBase::Monotonic, cycles),
+                        ),
+                        (
+                            "CLOCK_MONOTONIC_RAW",
+                            raw.hres_time(ClockBase::MonotonicRaw, cycles),
+                        ),
+                        (
+                            "CLOCK_REALTIME_COARSE",
+                            tk.hres_time(ClockBase::RealtimeCoarse, cycles),
+                        ),
+                        (
+                            "CLOCK_MONOTONIC_COARSE",
+                            tk.hres_time(ClockBase::MonotonicCoarse, cycles),
+                        ),
+                        ("CLOCK_BOOTTIME", tk.hres_time(ClockBase::Boottime, cycles)),
+                    ]
+                })
+        })
+        .ok()?
+        .ok()
+}
+
+/// Get the current time of `clock_id` as a POSIX `(tv_sec, tv_nsec)` pair.
+///
+/// Computed from the published data and the live counter exactly like the
+/// userspace vDSO, so a `clock_gettime` syscall handler using it returns the
+/// same values as the fast path. Returns `None` for unsupported clocks.
+/// Seconds beyond `i64::MAX` saturate rather than turn negative.
+pub fn vdso_timespec(clock_id: i32) -> Option<(i64, i64)> {
+    let ts = vdso_clock_gettime(clock_id)?;
+    Some((ts.sec.min(i64::MAX as u64) as i64, ts.nsec as i64))
+}
+
+/// Get the signed difference between the vDSO monotonic time, as userspace
+/// would read it now, and `reference_mono_ns` from the authoritative
+/// timekeeper.
+///
+/// A positive result means the vDSO runs ahead. Returns 0 if the vDSO time
+/// cannot be read.
+pub fn measure_drift_ns(reference_mono_ns: u64) -> i64 {
+    let Some(ts) = vdso_clock_gettime(ClockBase::Monotonic as i32) else {
+        return 0;
+    };
+    let vdso_ns = ts.sec as i128 * NANOS_PER_SEC as i128 + ts.nsec as i128;
+    (vdso_ns - reference_mono_ns as i128).clamp(i64::MIN as i128, i64::MAX as i128) as i64
+}
+
+/// Get the realtime step applied by the last update, in nanoseconds;
+/// negative if the wall clock was set back. Lets monitoring alert on large
+/// clock steps.
+pub fn last_wall_step_ns() -> i64 {
+    let data = vdso_time_data();
+    data.timekeeper().read(|_| data.last_wall_step_ns)
+}
+
+/// Get the number of vDSO updates skipped because the hardware counter read
+/// was behind the previously published `cycle_last`.
+pub fn cycle_backwards_events() -> u64 {
+    crate::vdso_time_data::cycle_backwards_events()
+}
+
+/// Get the number of per-update recalibrations rejected because the counter
+/// and monotonic deltas implied an implausible frequency.
+pub fn recalibration_rejects() -> u64 {
+    crate::vdso_time_data::recalibration_rejects()
+}
+
+/// Get the number of times vDSO seqlock readers in the kernel had to retry
+/// because an update was in progress.
+pub fn seqlock_read_retries() -> u64 {
+    crate::vdso_time_data::seqlock_read_retries()
+}
+
+/// Get how long ago the published vDSO time was last updated, in
+/// nanoseconds, taking the maximum over all clocks.
+///
+/// Derived from the live counter and each clock's `cycle_last`, so it reads
+/// 0 for clocks in `ClockMode::None`. A monitor can alert when this grows
+/// beyond the expected update period.
+pub fn time_since_last_update_ns() -> u64 {
+    let cycles = current_ticks();
+    vdso_time_data()
+        .clock_data
+        .iter()
+        .map(|clk| clk.read(|clk| clk.ns_since_update(cycles)))
+        .max()
+        .unwrap_or(0)
+}
+
+/// Get the longest the published time may go without an update before
+/// userspace readers compute wrong times, in nanoseconds.
+///
+/// The bound is the smaller of the counter delta whose `delta * mult` still
+/// fits the reader's 64-bit multiply and half the counter range, beyond which
+/// the update takes the delta for a backwards step. `u64::MAX` without a
+/// counter.
+pub fn max_update_interval_ns() -> u64 {
+    vdso_time_data().timekeeper().read(|clk| {
+        if clk.mult == 0 {
+            return u64::MAX;
+        }
+        let max_cycles = (u64::MAX / clk.mult as u64).min(clk.mask >> 1);
+        clk.cycles_to_ns(max_cycles)
+    })
+}
+
+/// Get the update period integrators should configure their timekeeping
+/// tick with, in nanoseconds: a quarter of [`max_update_interval_ns`], so a
+/// few late ticks neither reach the hard limit nor trip [`vdso_watchdog`],
+/// which fires at half. `u64::MAX` without a counter, as nothing overflows.
+pub fn recommended_update_interval_ns() -> u64 {
+    match max_update_interval_ns() {
+        u64::MAX => u64::MAX,
+        max => max / 4,
+    }
+}
+
+/// Last-resort check, meant for a slow (e.g. 1 Hz) timer, that forces an
+/// update if the published time has not been refreshed for half of
+/// [`max_update_interval_ns`], e.g. because the regular tick was suppressed.
+///
+/// `now_mono_ns` is the current monotonic time. Returns whether an update
+/// was forced.
+pub fn vdso_watchdog(now_mono_ns: u64) -> bool {
+    let data = vdso_time_data();
+    let last_update_mono_ns = data.timekeeper().read(|_| data.last_update_mono_ns);
+    let since_mono = now_mono_ns.saturating_sub(last_update_mono_ns);
+    let stale_ns = time_since_last_update_ns().max(since_mono);
+    if stale_ns <= max_update_interval_ns() / 2 {
+        return false;
+    }
+    log::warn!("vDSO watchdog: no update for {} ns, forcing one", stale_ns);
+    update_vdso_data();
+    true
+}
+
+/// Back-to-back counter reads sampled by [`calibrate_counter_overhead`].
+const OVERHEAD_SAMPLES: usize = 31;
+
+/// Measure the cost of one hardware counter read and publish it in the data
+/// page so userspace can compensate tight measurements.
+///
+/// Returns the median over a fixed number of back-to-back reads, in counter
+/// ticks.
+pub fn calibrate_counter_overhead() -> u64 {
+    use crate::vdso_data::read_arch_counter;
+
+    let mut samples = [0u64; OVERHEAD_SAMPLES];
+    for sample in samples.iter_mut() {
+        let start = read_arch_counter();
+        *sample = read_arch_counter().wrapping_sub(start);
+    }
+    samples.sort_unstable();
+    let median = samples[OVERHEAD_SAMPLES / 2];
+
+    let _writer = VdsoWriteGuard::acquire();
+    unsafe {
+        (*core::ptr::addr_of_mut!(VDSO_DATA))
+            .time_data
+            .counter_read_overhead = median.min(u32::MAX as u64) as u32;
+    }
+    median
+}
+
+/// Get the current sequence count of `clock_data[index]`.
+///
+/// An odd value means a writer is in the middle of an update, which helps
+/// explain anomalous readings captured alongside it. Read with `Relaxed`
+/// ordering. Panics if `index` is out of range.
+pub fn clock_seq(index: usize) -> u32 {
+    vdso_time_data().clock_data[index]
+        .seq
+        .load(Ordering::Relaxed)
+}
+
+/// Convert a raw counter delta to nanoseconds with the live scaling of the
+/// primary vDSO clock, read under the seqlock, exactly as userspace would.
+pub fn cycles_to_ns(cycles: u64) -> u64 {
+    vdso_time_data()
+        .timekeeper()
+        .read(|clk| clk.cycles_to_ns(cycles))
+}
+
+/// Get the hardware counter frequency in Hz the published scaling is derived
+/// from: the one given to [`set_counter_frequency_hz`], or else the
+/// platform's.
+pub fn counter_frequency_hz() -> u64 {
+    crate::vdso_time_data::counter_hz()
+}
+
+/// Deviation of the measured counter frequency from the configured one, in
+/// parts per million, beyond which [`estimate_counter_hz`] warns.
+const COUNTER_HZ_TOLERANCE_PPM: u64 = 1000;
+
+/// Multiple of the expected counter ticks of the interval after which
+/// [`estimate_counter_hz`] gives up on a reference clock that does not
+/// advance.
+const ESTIMATE_TIMEOUT_FACTOR: u64 = 4;
+/// Counter frequency the timeout of [`estimate_counter_hz`] assumes when none
+/// is known, in Hz; no supported counter runs faster.
+const MAX_COUNTER_HZ: u64 = 10_000_000_000;
+/// Spins without the counter advancing after which [`estimate_counter_hz`]
+/// gives up, as a stopped counter never reaches the timeout.
+const MAX_STALLED_SPINS: u32 = 1 << 20;
+
+/// Measure the counter frequency over `interval_ns` nanoseconds of the
+/// reference clock `reference_ns`, busy-waiting for the duration.
+///
+/// The reference must not be derived from the counter itself, as axplat's
+/// `monotonic_time_nanos` usually is, or the measurement only repeats the
+/// configured frequency; an RTC or HPET read is suitable. Warns if the
+/// result deviates from [`counter_frequency_hz`] by more than 0.1%, which
+/// means the platform misreports the frequency and the vDSO clocks drift.
+/// Meant for init or on demand, not hot paths.
+///
+/// Returns `None` if the reference did not cover the interval within
+/// `ESTIMATE_TIMEOUT_FACTOR` times the counter ticks the interval should
+/// take (at [`counter_frequency_hz`], or `MAX_COUNTER_HZ` if unknown), or if
+/// the counter stopped.
+pub fn estimate_counter_hz(interval_ns: u64, reference_ns: impl Fn() -> u64) -> Option<u64> {
+    let configured = counter_frequency_hz();
+    let timeout_hz = if configured != 0 {
+        configured
+    } else {
+        MAX_COUNTER_HZ
+    };
+    let timeout_ticks =
+        interval_ns.max(1) as u128 * timeout_hz as u128 * ESTIMATE_TIMEOUT_FACTOR as u128
+            / NANOS_PER_SEC as u128;
+    let timeout_ticks = timeout_ticks.clamp(1, u64::MAX as u128) as u64;
+    let Some(measured) =
+        measure_counter_hz(interval_ns, timeout_ticks, current_ticks, reference_ns)
+    else {
+        log::warn!(
+            "vDSO counter frequency estimate timed out: the reference clock did not advance {} ns \
+             within {} counter ticks",
+            interval_ns,
+            timeout_ticks
+        );
+        return None;
+    };
+
+    if measured.abs_diff(configured) as u128 * 1_000_000
+        > configured as u128 * COUNTER_HZ_TOLERANCE_PPM as u128
+    {
+        log::warn!(
+            "vDSO counter runs at about {} Hz, but the platform reports {} Hz",
+            measured,
+            configured
+        );
+    }
+    Some(measured)
+}
+
+/// Count the ticks of `counter` while `reference_ns` advances by
+/// `interval_ns`, see [`estimate_counter_hz`], giving up after
+/// `timeout_ticks` ticks or `MAX_STALLED_SPINS` spins without a tick.
+fn measure_counter_hz(
+    interval_ns: u64,
+    timeout_ticks: u64,
+    counter: impl Fn() -> u64,
+    reference_ns: impl Fn() -> u64,
+) -> Option<u64> {
+    let interval_ns = interval_ns.max(1);
+    let start_ticks = counter();
+    let start_ns = reference_ns();
+    let mut last_ticks = start_ticks;
+    let mut stalled = 0;
+    loop {
+        let elapsed_ns = reference_ns().saturating_sub(start_ns);
+        let now = counter();
+        let ticks = now.wrapping_sub(start_ticks);
+        if elapsed_ns >= interval_ns {
+            return Some((ticks as u128 * NANOS_PER_SEC as u128 / elapsed_ns as u128) as u64);
+        }
+        if ticks > timeout_ticks {
+            return None;
+        }
+        if now != last_ticks {
+            last_ticks = now;
+            stalled = 0;
+        } else if stalled == MAX_STALLED_SPINS {
+            return None;
+        } else {
+            stalled += 1;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+/// Duration of one platform counter tick in nanoseconds, rounded up, or
+/// `None` if the counter frequency is unknown.
+fn platform_timer_resolution_ns() -> Option<u32> {
+    let hz = counter_frequency_hz();
+    (hz != 0).then(|| NANOS_PER_SEC.div_ceil(hz).min(u32::MAX as u64) as u32)
+}
+
+/// Get the raw bytes of the VVAR data as userspace sees them, for hexdumps
+/// during bring-up.
+///
+/// This is a racy snapshot: an update may be in progress while the bytes are
+/// read. Use the seqlock-based readers to interpret time values.
+pub fn vvar_bytes() -> &'static [u8] {
+    unsafe { core::slice::from_raw_parts(core::ptr::addr_of!(VDSO_DATA) as *const u8, VVAR_BYTES) }
+}
+
+impl crate::vdso_data::VdsoData {
+    /// View the data as the raw bytes userspace maps, e.g. to stash it across
+    /// a warm reboot that preserves the counter.
+    pub fn to_bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self as *const Self as *const u8, VVAR_BYTES) }
+    }
+}
+
+/// Restore the clocks and timezone stashed with [`VdsoData::to_bytes`]
+/// into the live `VDSO_DATA`, e.g. after a warm reboot that preserved the
+/// counter.
+///
+/// Only [`VdsoTimeData::clock_data`] and the timezone are taken from
+/// `bytes`, see [`VdsoTimeData::restore`]; the rest of the data (CPU table,
+/// arch-specific pages) describes this boot and is kept, as is each clock's
+/// live clock mode. The data is restored in place under the writer lock, so
+/// the ~20 KiB image is never copied to the kernel stack.
+///
+/// Fails with `InvalidInput` if `bytes` is not exactly [`VVAR_BYTES`] long,
+/// and with `InvalidData` if a clock was captured mid-update (odd sequence
+/// count).
+///
+/// [`VdsoData::to_bytes`]: crate::vdso_data::VdsoData::to_bytes
+pub fn restore_vdso_data(bytes: &[u8]) -> AxResult<()> {
+    use core::mem::{offset_of, size_of};
+
+    use crate::{vdso_data::VdsoData, vdso_time_data::VdsoClock};
+
+    if bytes.len() != VVAR_BYTES {
+        return Err(AxError::InvalidInput);
+    }
+    let time_data = offset_of!(VdsoData, time_data);
+    let clocks = time_data + offset_of!(VdsoTimeData, clock_data);
+    let stashed: [VdsoClock; 2] = core::array::from_fn(|i| {
+        let at = clocks + i * size_of::<VdsoClock>();
+        unsafe { core::ptr::read_unaligned(bytes[at..].as_ptr() as *const VdsoClock) }
+    });
+    if stashed
+        .iter()
+        .any(|clk| clk.seq.load(Ordering::Relaxed) & 1 != 0)
+    {
+        return Err(AxError::InvalidData);
+    }
+    let i32_at = |at: usize| i32::from_ne_bytes(bytes[at..at + 4].try_into().unwrap());
+    let tz_minuteswest = i32_at(time_data + offset_of!(VdsoTimeData, tz_minuteswest));
+    let tz_dsttime = i32_at(time_data + offset_of!(VdsoTimeData, tz_dsttime));
+
+    let _writer = VdsoWriteGuard::acquire();
+    unsafe {
+        (*core::ptr::addr_of_mut!(VDSO_DATA)).time_data.restore(
+            &stashed,
+            tz_minuteswest,
+            tz_dsttime,
+        );
+    }
+    Ok(())
+}
+
+/// Translate a kernel virtual address, verifying that it lies in the linear
+/// mapping.
+///
+/// `virt_to_phys` silently returns a wrong address for memory outside the
+/// direct map, so translate back with `phys_to_virt` and compare.
+fn checked_virt_to_phys(vaddr: usize) -> AxResult<PhysAddr> {
+    let paddr = virt_to_phys(vaddr.into());
+    if phys_to_virt(paddr).as_usize() != vaddr {
+        log::error!("vDSO: {:#x} is not in the kernel linear mapping", vaddr);
+        return Err(AxError::BadAddress);
+    }
+    Ok(paddr)
+}
+
+/// Get the physical address and page-rounded size of the time data page
+/// alone, as opposed to the whole VVAR region described by
+/// [`vdso_segments`].
+pub fn vdso_data_region() -> (PhysAddr, usize) {
+    let data_ptr = core::ptr::addr_of!(VDSO_DATA) as usize;
+    let size = core::mem::size_of::<VdsoTimeData>().next_multiple_of(PAGE_SIZE_4K);
+    (virt_to_phys(data_ptr.into()), size)
+}
+
 /// Get the physical address of vDSO data for mapping to userspace
 pub fn vdso_data_paddr() -> usize {
-    let data_ptr = core::ptr::addr_of!(VDSO_DATA) as usize;
-    virt_to_phys(data_ptr.into()).into()
+    vdso_data_region().0.into()
 }
 
 /// Information about loaded vDSO pages for userspace mapping and auxv update.
 pub type VdsoPageInfo = (
-    axplat::mem::PhysAddr,
+    PhysAddr,
     &'static [u8],
     usize,
     usize,
-    Option<(usize, usize)>,
+    Option<(usize, usize, usize)>,
 );
 
+/// Access permissions intended for a vDSO user mapping.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VdsoSegmentPerm {
+    /// vDSO code pages: readable and executable.
+    ReadExec,
+    /// VVAR data pages: read-only.
+    ReadOnly,
+}
+
+/// A physically contiguous part of the vDSO to be mapped into userspace.
+#[derive(Debug, Clone, Copy)]
+pub struct VdsoSegment {
+    /// Physical address of the first page.
+    pub paddr: PhysAddr,
+    /// Size in bytes, a multiple of the page size.
+    pub size: usize,
+    /// Offset of the mapping relative to the page-aligned vDSO code base.
+    pub user_offset: isize,
+    /// Intended access permissions.
+    pub perm: VdsoSegmentPerm,
+}
+
+/// The vDSO code and VVAR data as separate mappable segments.
+///
+/// The VVAR pages sit directly below the code, as expected by the vDSO
+/// linker script.
+#[derive(Debug, Clone, Copy)]
+pub struct VdsoSegments {
+    pub code: VdsoSegment,
+    pub vvar: VdsoSegment,
+}
+
+/// Number of VVAR pages reserved below the vDSO code, see [`set_vvar_pages`].
+static VVAR_PAGE_COUNT: AtomicUsize = AtomicUsize::new(VVAR_PAGES);
+
+/// Reserve `pages` VVAR pages below the vDSO code instead of the arch default
+/// `VVAR_PAGES`, to match a userspace vDSO linked for a different layout.
+///
+/// The data stays at the bottom of the region. [`vdso_segments`] only maps
+/// the pages backed by [`VDSO_DATA`], so every page above them is a hole
+/// that faults rather than exposing kernel memory. Fails with `InvalidInput`
+/// if `pages` cannot hold [`VVAR_BYTES`].
+pub fn set_vvar_pages(pages: usize) -> AxResult<()> {
+    if pages < VVAR_BYTES.div_ceil(PAGE_SIZE_4K) {
+        return Err(AxError::InvalidInput);
+    }
+    VVAR_PAGE_COUNT.store(pages, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Get the number of VVAR pages reserved below the vDSO code.
+pub fn vvar_pages() -> usize {
+    VVAR_PAGE_COUNT.load(Ordering::Relaxed)
+}
+
+/// What each page of the VVAR region holds on the current arch, as page
+/// indices from the start of the region.
+///
+/// The pages follow the order of the Linux generic vDSO data store: time
+/// data, time namespace, getrandom state, then the arch data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VvarLayout {
+    /// Pages reserved for the region, the arch's `VVAR_PAGES` unless
+    /// overridden with [`set_vvar_pages`].
+    pub total_pages: usize,
+    /// Pages backed by [`VDSO_DATA`], counted from the start of the region.
+    /// Pages at or beyond this index have no backing, so [`vdso_segments`]
+    /// leaves them unmapped.
+    pub backed_pages: usize,
+    /// Page holding `VdsoTimeData`.
+    pub time_data: usize,
+    /// Time namespace page.
+    pub timens: usize,
+    /// getrandom state page.
+    pub rng: usize,
+    /// First arch data page.
+    pub arch: usize,
+    /// Page holding the pvclock array, x86_64 only.
+    pub pvclock: Option<usize>,
+}
+
+/// Describe the VVAR region of the current arch, see [`VvarLayout`], with
+/// the page count currently reserved, see [`vvar_pages`].
+pub fn vvar_layout() -> VvarLayout {
+    VvarLayout {
+        total_pages: vvar_pages(),
+        ..crate::vdso_data::VdsoData::VVAR_LAYOUT
+    }
+}
+
+const _: () = {
+    let layout = crate::vdso_data::VdsoData::VVAR_LAYOUT;
+    assert!(layout.backed_pages <= layout.total_pages);
+    // The mapped pages must cover all of `VDSO_DATA`.
+    assert!(layout.backed_pages == VVAR_BYTES.div_ceil(PAGE_SIZE_4K));
+};
+
+/// Describe the vDSO code prepared by [`prepare_vdso_pages`] and the VVAR
+/// data as two segments to be mapped at `vdso_base + user_offset`.
+///
+/// The VVAR segment starts at the bottom of the reserved region and only
+/// covers the pages backed by [`VDSO_DATA`]; the rest of the region is left
+/// unmapped so userspace cannot read the kernel memory that follows it.
+pub fn vdso_segments(pages: &VdsoPageInfo) -> VdsoSegments {
+    let (code_paddr, _, code_size, ..) = *pages;
+    let vvar_pages = vvar_pages();
+    let vvar_size = vvar_layout().backed_pages * PAGE_SIZE_4K;
+    VdsoSegments {
+        code: VdsoSegment {
+            paddr: code_paddr,
+            size: code_size,
+            user_offset: 0,
+            perm: VdsoSegmentPerm::ReadExec,
+        },
+        vvar: VdsoSegment {
+            paddr: vdso_data_paddr().into(),
+            size: vvar_size,
+            user_offset: -((vvar_pages * PAGE_SIZE_4K) as isize),
+            perm: VdsoSegmentPerm::ReadOnly,
+        },
+    }
+}
+
+/// Page size the vDSO is mapped with, which must match `AT_PAGESZ`.
+pub const fn vdso_page_size() -> usize {
+    PAGE_SIZE_4K
+}
+
 /// Load vDSO into the given user address space and update auxv accordingly.
 pub fn prepare_vdso_pages(vdso_kstart: usize, vdso_kend: usize) -> AxResult<VdsoPageInfo> {
-    let orig_vdso_len = vdso_kend - vdso_kstart;
+    prepare_vdso_pages_aligned(vdso_kstart, vdso_kend, PAGE_SIZE_4K)
+}
+
+/// Like [`prepare_vdso_pages`], but place the code pages on an `align`-byte
+/// boundary (e.g. 2 MiB), so the region around them can be mapped with huge
+/// pages.
+///
+/// An image not already on such a boundary is copied into an allocation of
+/// that alignment, rounded up to a multiple of `align`, and returned as
+/// `(alloc_vaddr, num_pages, align)`; release it with [`release_vdso_pages`]
+/// or a `VdsoAllocGuard`.
+///
+/// Errors:
+/// - `InvalidInput`: `align` is not a power of two of at least the page size,
+///   or `vdso_kend` lies below `vdso_kstart`.
+/// - `InvalidExecutable`: the image is not a usable vDSO, see `check_image`,
+///   `check_load_alignment` and `check_clock_mode`.
+/// - `OutOfRange`: the image exceeds [`MAX_VDSO_SIZE`], or the aligned copy is
+///   too large to lay out.
+/// - `NoMemory`: the aligned copy could not be allocated.
+/// - `BadAddress`: the allocator returned a misaligned buffer (also a debug
+///   assertion), or one outside the linear mapping.
+/// - `BadState`: the copy would not fit its buffer, an internal error.
+pub fn prepare_vdso_pages_aligned(
+    vdso_kstart: usize,
+    vdso_kend: usize,
+    align: usize,
+) -> AxResult<VdsoPageInfo> {
+    if !align.is_power_of_two() || align < PAGE_SIZE_4K {
+        return Err(AxError::InvalidInput);
+    }
+    let orig_vdso_len = vdso_kend
+        .checked_sub(vdso_kstart)
+        .ok_or(AxError::InvalidInput)?;
+    if orig_vdso_len > MAX_VDSO_SIZE {
+        log::error!(
+            "vDSO image of {:#x} bytes exceeds the {:#x} byte limit",
+            orig_vdso_len,
+            MAX_VDSO_SIZE
+        );
+        return Err(AxError::OutOfRange);
+    }
     let orig_page_off = vdso_kstart & (PAGE_SIZE_4K - 1);
 
-    if orig_page_off == 0 {
-        // Already page aligned: use original memory region directly.
-        let vdso_paddr_page = virt_to_phys(vdso_kstart.into());
+    let elf_bytes = unsafe { core::slice::from_raw_parts(vdso_kstart as *const u8, orig_vdso_len) };
+    crate::elf::check_image(elf_bytes)?;
+    crate::elf::check_load_alignment(elf_bytes, vdso_page_size())?;
+    crate::elf::check_clock_mode(elf_bytes, current_clock_mode())?;
+
+    if vdso_kstart & (align - 1) == 0 {
+        // Already aligned: use original memory region directly.
+        let vdso_paddr_page = checked_virt_to_phys(vdso_kstart)?;
         let vdso_size = (vdso_kend - vdso_kstart + PAGE_SIZE_4K - 1) & !(PAGE_SIZE_4K - 1);
-        let vdso_bytes =
-            unsafe { core::slice::from_raw_parts(vdso_kstart as *const u8, orig_vdso_len) };
-        Ok((vdso_paddr_page, vdso_bytes, vdso_size, 0usize, None))
+        Ok((vdso_paddr_page, elf_bytes, vdso_size, 0usize, None))
     } else {
         let total_size = orig_vdso_len + orig_page_off;
-        let num_pages = total_size.div_ceil(PAGE_SIZE_4K);
-        let vdso_size = num_pages * PAGE_SIZE_4K;
+        let vdso_size = total_size.next_multiple_of(align);
+        let num_pages = vdso_size / PAGE_SIZE_4K;
 
-        let layout = match Layout::from_size_align(vdso_size, PAGE_SIZE_4K) {
+        let layout = match Layout::from_size_align(vdso_size, align) {
             Ok(l) => l,
-            Err(_) => return Err(AxError::InvalidExecutable),
+            Err(_) => return Err(AxError::OutOfRange),
         };
         let alloc_ptr = unsafe { alloc_zeroed(layout) };
         if alloc_ptr.is_null() {
-            return Err(AxError::InvalidExecutable);
+            return Err(AxError::NoMemory);
+        }
+        // A misaligned buffer would make the user mapping start at the wrong
+        // physical page, so refuse it rather than map corrupt contents.
+        let misaligned = (alloc_ptr as usize) & (align - 1) != 0;
+        debug_assert!(
+            !misaligned,
+            "vDSO allocation {alloc_ptr:p} is not {align:#x} aligned"
+        );
+        if misaligned {
+            unsafe { dealloc(alloc_ptr, layout) };
+            return Err(AxError::BadAddress);
+        }
+        let alloc_vaddr = alloc_ptr as usize;
+        let vdso_paddr_page = match checked_virt_to_phys(alloc_vaddr) {
+            Ok(paddr) => paddr,
+            Err(e) => {
+                unsafe { dealloc(alloc_ptr, layout) };
+                return Err(e);
+            }
+        };
+        // The copy must fit behind the in-page offset; guaranteed by the
+        // rounding of `vdso_size`, but checked as it guards a raw write.
+        if orig_vdso_len > vdso_size - orig_page_off {
+            unsafe { dealloc(alloc_ptr, layout) };
+            return Err(AxError::BadState);
         }
         // destination start where vdso_start should reside
         let dest = unsafe { alloc_ptr.add(orig_page_off) };
         let src = vdso_kstart as *const u8;
+        // `copy_nonoverlapping` requires disjoint ranges, which holds as long
+        // as the destination is a fresh allocation and not the image itself.
+        debug_assert!(
+            dest as usize + orig_vdso_len <= vdso_kstart || vdso_kend <= dest as usize,
+            "vDSO copy destination overlaps the source image"
+        );
         unsafe { core::ptr::copy_nonoverlapping(src, dest, orig_vdso_len) };
-        let alloc_vaddr = alloc_ptr as usize;
-        let vdso_paddr_page = virt_to_phys(alloc_vaddr.into());
         let vdso_bytes = unsafe { core::slice::from_raw_parts(dest as *const u8, orig_vdso_len) };
         Ok((
             vdso_paddr_page,
             vdso_bytes,
             vdso_size,
             orig_page_off,
-            Some((alloc_vaddr, num_pages)),
+            Some((alloc_vaddr, num_pages, align)),
         ))
     }
 }
 
-/// Calculate ASLR-randomized vDSO user address
-pub fn calculate_vdso_aslr_addr(
-    vdso_kstart: usize,
-    vdso_kend: usize,
-    vdso_page_offset: usize,
-) -> (usize, usize) {
+/// Free the copy [`prepare_vdso_pages`] or [`prepare_vdso_pages_aligned`]
+/// made, given the `(alloc_vaddr, num_pages, align)` it returned.
+///
+/// # Safety
+///
+/// The allocation must come from one of those functions, must no longer be
+/// mapped to userspace, and must not be released twice.
+pub unsafe fn release_vdso_pages(alloc: (usize, usize, usize)) {
+    let (alloc_vaddr, num_pages, align) = alloc;
+    if let Ok(layout) = Layout::from_size_align(num_pages * PAGE_SIZE_4K, align) {
+        unsafe { dealloc(alloc_vaddr as *mut u8, layout) };
+    }
+}
+
+/// Lowest user address the vDSO is placed at.
+const VDSO_USER_ADDR_BASE: usize = 0x7f00_0000;
+/// Number of pages the vDSO base is randomized over.
+const VDSO_ASLR_PAGES: usize = 256;
+
+/// How often the vDSO ASLR offset is re-randomized.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AslrScope {
+    /// A fresh offset for every exec, as Linux does.
+    PerExec = 0,
+    /// One offset chosen on first use after boot and shared by all processes.
+    PerBoot = 1,
+}
+
+/// Selected [`AslrScope`].
+static ASLR_SCOPE: AtomicU8 = AtomicU8::new(AslrScope::PerExec as u8);
+/// Page offset shared under [`AslrScope::PerBoot`], `usize::MAX` until drawn.
+static PER_BOOT_ASLR_PAGE: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// Choose how often [`calculate_vdso_aslr`] re-randomizes the vDSO base.
+pub fn set_aslr_scope(scope: AslrScope) {
+    ASLR_SCOPE.store(scope as u8, Ordering::Relaxed);
+}
+
+/// Draw a random page offset below `VDSO_ASLR_PAGES`.
+fn random_aslr_page(vdso_kstart: usize, vdso_kend: usize) -> usize {
     use rand_core::RngCore;
     use rand_pcg::Pcg64Mcg;
 
-    const VDSO_USER_ADDR_BASE: usize = 0x7f00_0000;
-    const VDSO_ASLR_PAGES: usize = 256;
-
     let seed: u128 = (monotonic_time_nanos() as u128)
         ^ ((vdso_kstart as u128).rotate_left(13))
         ^ ((vdso_kend as u128).rotate_left(37));
     let mut rng = Pcg64Mcg::new(seed);
-    let page_off: usize = (rng.next_u64() as usize) % VDSO_ASLR_PAGES;
+    (rng.next_u64() as usize) % VDSO_ASLR_PAGES
+}
+
+/// Page offset forced by [`set_fixed_aslr_offset`], `usize::MAX` if unset.
+static FIXED_ASLR_PAGE: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+/// Make [`calculate_vdso_aslr`] place the vDSO `n` pages above its
+/// lowest address on every call, or restore randomization with `None`.
+///
+/// Meant for golden images and record/replay debugging, which need the vDSO
+/// at the same address on every run. A fixed offset defeats vDSO ASLR, so
+/// production kernels must leave this unset.
+///
+/// Returns `InvalidInput`, leaving the previous setting, for an offset
+/// outside the randomized range of `VDSO_ASLR_PAGES` pages, which could
+/// place the vDSO outside the window reserved for it.
+pub fn set_fixed_aslr_offset(pages: Option<usize>) -> AxResult<()> {
+    let page = match pages {
+        Some(n) if n >= VDSO_ASLR_PAGES => return Err(AxError::InvalidInput),
+        Some(n) => n,
+        None => usize::MAX,
+    };
+    FIXED_ASLR_PAGE.store(page, Ordering::Relaxed);
+    Ok(())
+}
+
+/// ASLR-randomized vDSO user addresses, see [`calculate_vdso_aslr`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VdsoAslrResult {
+    /// Page-aligned address the first code page maps at; the VVAR pages go
+    /// below it, see [`vdso_segments`].
+    pub page_base: usize,
+    /// Address of the ELF header, `page_base` plus the image's in-page
+    /// offset; the `AT_SYSINFO_EHDR` auxv value.
+    pub vdso_addr: usize,
+}
+
+/// Calculate ASLR-randomized vDSO user addresses
+pub fn calculate_vdso_aslr(
+    vdso_kstart: usize,
+    vdso_kend: usize,
+    vdso_page_offset: usize,
+) -> VdsoAslrResult {
+    let fixed = FIXED_ASLR_PAGE.load(Ordering::Relaxed);
+    let page_off = if fixed != usize::MAX {
+        fixed
+    } else if ASLR_SCOPE.load(Ordering::Relaxed) == AslrScope::PerBoot as u8 {
+        let drawn = random_aslr_page(vdso_kstart, vdso_kend);
+        match PER_BOOT_ASLR_PAGE.compare_exchange(
+            usize::MAX,
+            drawn,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => drawn,
+            Err(shared) => shared,
+        }
+    } else {
+        random_aslr_page(vdso_kstart, vdso_kend)
+    };
     let base_addr = VDSO_USER_ADDR_BASE + page_off * PAGE_SIZE_4K;
     let vdso_addr = if vdso_page_offset != 0 {
         base_addr.wrapping_add(vdso_page_offset)
@@ -116,5 +1289,625 @@ pub fn calculate_vdso_aslr_addr(
         base_addr
     };
 
-    (base_addr, vdso_addr)
+    VdsoAslrResult {
+        page_base: base_addr,
+        vdso_addr,
+    }
+}
+
+/// Calculate ASLR-randomized vDSO user addresses as a
+/// `(page_base, vdso_addr)` tuple.
+#[deprecated(note = "use `calculate_vdso_aslr`, whose fields are named")]
+pub fn calculate_vdso_aslr_addr(
+    vdso_kstart: usize,
+    vdso_kend: usize,
+    vdso_page_offset: usize,
+) -> (usize, usize) {
+    let aslr = calculate_vdso_aslr(vdso_kstart, vdso_kend, vdso_page_offset);
+    (aslr.page_base, aslr.vdso_addr)
+}
+
+/// Get the byte offset of `user_addr` into the vDSO code pages mapped at
+/// `vdso_base`, e.g. to find the page backing a fault address.
+///
+/// `vdso_base` may be either address returned by [`calculate_vdso_aslr`]: it is
+/// rounded down to its page, so the in-page offset of an unaligned image is
+/// part of the result, matching the layout of the pages from
+/// [`prepare_vdso_pages`]. `user_addr` must not be below that page; VVAR
+/// addresses are located with [`vdso_segments`].
+pub fn vdso_user_to_offset(user_addr: usize, vdso_base: usize) -> usize {
+    let map_base = vdso_base & !(PAGE_SIZE_4K - 1);
+    debug_assert!(
+        user_addr >= map_base,
+        "user address {user_addr:#x} below the vDSO at {map_base:#x}"
+    );
+    user_addr - map_base
+}
+
+/// Where a process maps the vDSO and the addresses derived from it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VdsoPlacement {
+    /// Page-aligned user address the code pages are mapped at; the VVAR
+    /// pages go below it, see [`vdso_segments`].
+    pub map_base: usize,
+    /// User address of the ELF header, the `AT_SYSINFO_EHDR` auxv value.
+    pub sysinfo_ehdr: usize,
+    /// Value added to link-time symbol addresses to get user addresses.
+    pub load_bias: usize,
+}
+
+/// Combine the addresses returned by [`calculate_vdso_aslr`] with the ELF
+/// image into the final placement.
+pub fn finalize_vdso_placement(aslr: VdsoAslrResult, elf_bytes: &[u8]) -> VdsoPlacement {
+    VdsoPlacement {
+        map_base: aslr.page_base,
+        sysinfo_ehdr: aslr.vdso_addr,
+        load_bias: crate::elf::vdso_load_bias(aslr.vdso_addr, elf_bytes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        alloc::{GlobalAlloc, System},
+        cell::Cell,
+    };
+
+    use super::*;
+
+    /// The arch's counter mode (`Tsc`, `Cntvct` or `Csr`), 1 on every arch.
+    const COUNTER_MODE: ClockMode = ClockMode::from_raw(1).unwrap();
+
+    /// Bytes page-aligned allocations are moved off their alignment by while
+    /// [`MisalignPages`] is armed.
+    const MISALIGN_BY: usize = 64;
+
+    std::thread_local! {
+        /// Whether this thread's page-aligned allocations are misaligned.
+        static MISALIGN: Cell<bool> = const { Cell::new(false) };
+        /// The last misaligned allocation, so it can be freed.
+        static MISALIGNED: Cell<usize> = const { Cell::new(0) };
+    }
+
+    /// The system allocator, except that it returns page-aligned buffers
+    /// [`MISALIGN_BY`] bytes off their alignment on a thread that armed it.
+    struct MisalignPages;
+
+    unsafe impl GlobalAlloc for MisalignPages {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            if layout.align() < PAGE_SIZE_4K || !MISALIGN.with(Cell::get) {
+                return unsafe { System.alloc(layout) };
+            }
+            let padded = Layout::from_size_align(layout.size() + MISALIGN_BY, layout.align());
+            let ptr = unsafe { System.alloc(padded.unwrap()) };
+            if ptr.is_null() {
+                return ptr;
+            }
+            let ptr = unsafe { ptr.add(MISALIGN_BY) };
+            MISALIGNED.with(|last| last.set(ptr as usize));
+            ptr
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            if ptr as usize == MISALIGNED.with(Cell::get) {
+                MISALIGNED.with(|last| last.set(0));
+                let padded = Layout::from_size_align(layout.size() + MISALIGN_BY, layout.align());
+                unsafe { System.dealloc(ptr.sub(MISALIGN_BY), padded.unwrap()) };
+            } else {
+                unsafe { System.dealloc(ptr, layout) };
+            }
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: MisalignPages = MisalignPages;
+
+    /// The embedded image, copied `page_off` bytes into a fresh page-aligned
+    /// buffer that lives for the rest of the test run. The ELF parser needs
+    /// `page_off` to keep the header 8-byte aligned.
+    fn image_at(page_off: usize) -> (usize, usize) {
+        let image = crate::embed::embedded_vdso();
+        let len = image.len();
+        let layout = Layout::from_size_align(page_off + len, PAGE_SIZE_4K).unwrap();
+        let buf = unsafe { alloc_zeroed(layout) };
+        let kstart = buf as usize + page_off;
+        unsafe { core::ptr::copy_nonoverlapping(image.as_ptr(), kstart as *mut u8, len) };
+        (kstart, kstart + len)
+    }
+
+    #[test]
+    fn aligned_image_is_used_in_place() {
+        let (kstart, kend) = image_at(0);
+        let (_, bytes, size, page_off, alloc) = prepare_vdso_pages(kstart, kend).unwrap();
+        assert_eq!(bytes.as_ptr() as usize, kstart);
+        assert_eq!(bytes.len(), kend - kstart);
+        assert_eq!(size, (kend - kstart).next_multiple_of(PAGE_SIZE_4K));
+        assert_eq!(page_off, 0);
+        assert!(alloc.is_none());
+    }
+
+    #[test]
+    fn offset_image_is_copied_like_the_aligned_one() {
+        let (aligned_start, aligned_end) = image_at(0);
+        let (_, aligned, ..) = prepare_vdso_pages(aligned_start, aligned_end).unwrap();
+        for page_off in [8, 0x128, PAGE_SIZE_4K - 64] {
+            let (kstart, kend) = image_at(page_off);
+            let (_, bytes, size, off, alloc) = prepare_vdso_pages(kstart, kend).unwrap();
+            let alloc = alloc.expect("an unaligned image must be copied");
+            // Same content at the same in-page offset of a fresh page.
+            assert_eq!(bytes, aligned);
+            assert_eq!(off, page_off);
+            assert_eq!(bytes.as_ptr() as usize, alloc.0 + page_off);
+            assert_eq!(alloc.0 & (PAGE_SIZE_4K - 1), 0);
+            assert_eq!((alloc.1 * PAGE_SIZE_4K, alloc.2), (size, PAGE_SIZE_4K));
+            assert!(page_off + bytes.len() <= size);
+            unsafe { release_vdso_pages(alloc) };
+        }
+    }
+
+    #[test]
+    #[cfg_attr(debug_assertions, should_panic(expected = "is not 0x1000 aligned"))]
+    fn misaligned_allocation_is_refused() {
+        let (kstart, kend) = image_at(8);
+        MISALIGN.with(|armed| armed.set(true));
+        let result = prepare_vdso_pages(kstart, kend);
+        MISALIGN.with(|armed| armed.set(false));
+        assert_eq!(result.map(|_| ()), Err(AxError::BadAddress));
+        assert_eq!(MISALIGNED.with(Cell::get), 0, "the buffer was not freed");
+    }
+
+    #[test]
+    fn unusable_inputs_have_distinct_errors() {
+        let (kstart, kend) = image_at(0);
+        let err = |kstart, kend, align| prepare_vdso_pages_aligned(kstart, kend, align).err();
+        assert_eq!(
+            err(kstart, kend, 3 * PAGE_SIZE_4K),
+            Some(AxError::InvalidInput)
+        );
+        assert_eq!(err(kend, kstart, PAGE_SIZE_4K), Some(AxError::InvalidInput));
+        // Checked before the image is read, so the bogus end is never touched.
+        let oversized = kstart + MAX_VDSO_SIZE + 1;
+        assert_eq!(
+            err(kstart, oversized, PAGE_SIZE_4K),
+            Some(AxError::OutOfRange)
+        );
+        assert_eq!(
+            err(kstart, kstart, PAGE_SIZE_4K),
+            Some(AxError::InvalidExecutable)
+        );
+        let (junk, _) = image_at(0);
+        unsafe { core::ptr::write_bytes(junk as *mut u8, 0, 4) };
+        assert_eq!(
+            err(junk, kend - kstart + junk, PAGE_SIZE_4K),
+            Some(AxError::InvalidExecutable)
+        );
+    }
+
+    #[test]
+    fn huge_alignment_copies_a_page_aligned_image() {
+        const ALIGN: usize = 2 * 1024 * 1024;
+        let (kstart, kend) = image_at(0);
+        if kstart & (ALIGN - 1) == 0 {
+            return;
+        }
+        let (_, bytes, size, off, alloc) = prepare_vdso_pages_aligned(kstart, kend, ALIGN).unwrap();
+        let alloc = alloc.expect("an image off the huge-page boundary must be copied");
+        assert_eq!(bytes.as_ptr() as usize & (ALIGN - 1), 0);
+        assert_eq!((off, size, alloc.2), (0, ALIGN, ALIGN));
+        unsafe { release_vdso_pages(alloc) };
+    }
+
+    #[test]
+    fn restore_rejects_bad_images_and_advances_seq() {
+        let _globals = crate::lock_globals();
+        let live = || unsafe { &*core::ptr::addr_of!(VDSO_DATA) };
+        let mut image = live().to_bytes().to_vec();
+        assert_eq!(restore_vdso_data(&image[1..]), Err(AxError::InvalidInput));
+
+        let seq_at = core::mem::offset_of!(crate::vdso_data::VdsoData, time_data)
+            + core::mem::offset_of!(VdsoTimeData, clock_data);
+        image[seq_at] |= 1;
+        assert_eq!(restore_vdso_data(&image), Err(AxError::InvalidData));
+        image[seq_at] &= !1;
+
+        let before = live().time_data.clock_data[0].seq.load(Ordering::Relaxed);
+        restore_vdso_data(&image).unwrap();
+        let after = live().time_data.clock_data[0].seq.load(Ordering::Relaxed);
+        assert_eq!(after, before.wrapping_add(2));
+    }
+
+    #[test]
+    fn fixed_aslr_offset_stays_in_the_window() {
+        let _globals = crate::lock_globals();
+        let (kstart, kend) = (0x1000, 0x3000);
+        set_fixed_aslr_offset(Some(VDSO_ASLR_PAGES - 1)).unwrap();
+        let top = calculate_vdso_aslr(kstart, kend, 0);
+        assert_eq!(
+            top.page_base,
+            VDSO_USER_ADDR_BASE + (VDSO_ASLR_PAGES - 1) * PAGE_SIZE_4K
+        );
+        for pages in [VDSO_ASLR_PAGES, usize::MAX] {
+            assert_eq!(
+                set_fixed_aslr_offset(Some(pages)),
+                Err(AxError::InvalidInput)
+            );
+            assert_eq!(calculate_vdso_aslr(kstart, kend, 0), top);
+        }
+        set_fixed_aslr_offset(None).unwrap();
+    }
+
+    #[test]
+    fn vvar_layout_follows_the_reserved_page_count() {
+        let _globals = crate::lock_globals();
+        let default = vvar_layout();
+        assert_eq!(default.total_pages, VVAR_PAGES);
+        assert_eq!(
+            set_vvar_pages(default.backed_pages - 1),
+            Err(AxError::InvalidInput)
+        );
+        assert_eq!(vvar_layout(), default);
+
+        set_vvar_pages(VVAR_PAGES + 2).unwrap();
+        let layout = vvar_layout();
+        set_vvar_pages(VVAR_PAGES).unwrap();
+        assert_eq!(
+            layout,
+            VvarLayout {
+                total_pages: VVAR_PAGES + 2,
+                ..default
+            }
+        );
+    }
+
+    #[test]
+    fn counter_estimate_gives_up_on_a_stuck_reference() {
+        use core::cell::Cell;
+
+        // 24 ticks per microsecond of the reference: 24 MHz.
+        let ticks = Cell::new(0u64);
+        let counter = || ticks.replace(ticks.get() + 24);
+        let ns = Cell::new(0u64);
+        let reference = || ns.replace(ns.get() + 1000);
+        assert_eq!(
+            measure_counter_hz(1_000_000, 96_000, counter, reference),
+            Some(24_000_000)
+        );
+
+        // The reference never moves: bounded by the counter ticks.
+        ticks.set(0);
+        assert_eq!(measure_counter_hz(1_000_000, 96_000, counter, || 0), None);
+        assert!(ticks.get() <= 96_000 + 2 * 24);
+        // Nor does the counter.
+        assert_eq!(measure_counter_hz(1_000_000, 96_000, || 7, || 0), None);
+    }
+
+    #[test]
+    fn restore_takes_only_the_clocks_and_timezone() {
+        use core::mem::{offset_of, size_of};
+
+        use crate::{
+            vdso_data::VdsoData,
+            vdso_time_data::{VdsoClock, timestamp_from_ns},
+        };
+
+        let _globals = crate::lock_globals();
+        let live = || unsafe { &*core::ptr::addr_of!(VDSO_DATA) };
+        let mut image = live().to_bytes().to_vec();
+        let time_data = offset_of!(VdsoData, time_data);
+        let clocks = time_data + offset_of!(VdsoTimeData, clock_data);
+        let modes = live()
+            .time_data
+            .clock_data
+            .each_ref()
+            .map(|clk| clk.clock_mode);
+        for (i, mode) in [modes[0], 99].into_iter().enumerate() {
+            let mut clk = VdsoClock::new();
+            clk.clock_mode = mode;
+            clk.set_scaling(41_666_667, 20);
+            clk.cycle_last.store(1000, Ordering::Relaxed);
+            clk.time_data[ClockBase::Monotonic as usize] = timestamp_from_ns(7_000_000_000, 20);
+            let at = clocks + i * size_of::<VdsoClock>();
+            unsafe { core::ptr::write_unaligned(image[at..].as_mut_ptr() as *mut VdsoClock, clk) };
+        }
+        let tz = time_data + offset_of!(VdsoTimeData, tz_minuteswest);
+        image[tz..tz + 4].copy_from_slice(&(-60i32).to_ne_bytes());
+        // Neither the CPU table nor anything past the time data is restored.
+        let cpu_info = time_data + offset_of!(VdsoTimeData, cpu_info);
+        image[cpu_info] ^= 0xff;
+        let last = image.len() - 1;
+        image[last] ^= 0xff;
+
+        restore_vdso_data(&image).unwrap();
+        let data = live();
+        let [tk, raw] = &data.time_data.clock_data;
+        assert_eq!(tk.clock_mode, modes[0]);
+        assert_eq!(tk.cycle_last.load(Ordering::Relaxed), 1000);
+        assert_eq!((tk.mult, tk.shift), (41_666_667, 20));
+        assert_eq!(
+            tk.time_data[ClockBase::Monotonic as usize],
+            timestamp_from_ns(7_000_000_000, 20)
+        );
+        // An unknown stashed mode keeps the live one and drops the counter.
+        assert_eq!(raw.clock_mode, modes[1]);
+        assert_eq!((raw.cycle_last.load(Ordering::Relaxed), raw.mult), (0, 0));
+        assert_eq!(data.time_data.timezone().0, -60);
+        assert_ne!(data.to_bytes()[cpu_info], image[cpu_info]);
+        assert_ne!(data.to_bytes()[last], image[last]);
+
+        unsafe {
+            (*core::ptr::addr_of_mut!(VDSO_DATA))
+                .time_data
+                .tz_minuteswest = 0
+        };
+    }
+
+    #[test]
+    fn concurrent_updates_leave_the_seqlock_even() {
+        use std::sync::atomic::AtomicBool;
+
+        const WRITERS: usize = 4;
+        const UPDATES: usize = 2000;
+        let _globals = crate::lock_globals();
+        let done = AtomicBool::new(false);
+        std::thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(|| {
+                    let mut reads = 0u64;
+                    while !done.load(Ordering::Relaxed) {
+                        for clk in &vdso_time_data().clock_data {
+                            clk.read(|clk| clk.mult);
+                        }
+                        reads += 1;
+                    }
+                    assert!(reads > 0);
+                });
+            }
+            let writers: Vec<_> = (0..WRITERS)
+                .map(|_| s.spawn(|| (0..UPDATES).for_each(|_| update_vdso_data())))
+                .collect();
+            writers.into_iter().for_each(|w| w.join().unwrap());
+            done.store(true, Ordering::Relaxed);
+        });
+        for index in 0..vdso_time_data().clock_data.len() {
+            assert_eq!(clock_seq(index) & 1, 0, "clock {index} left mid-update");
+        }
+    }
+
+    #[test]
+    fn concurrent_updates_serialize() {
+        const WRITERS: usize = 4;
+        const UPDATES: usize = 1000;
+        let _globals = crate::lock_globals();
+        let before = [clock_seq(0), clock_seq(1)];
+        std::thread::scope(|s| {
+            for _ in 0..WRITERS {
+                s.spawn(|| (0..UPDATES).for_each(|_| update_vdso_data()));
+            }
+        });
+        // Every update is one write section per clock; an interleaved pair
+        // of writers would lose increments or leave the count odd.
+        for (index, start) in before.into_iter().enumerate() {
+            let expected = start.wrapping_add((2 * WRITERS * UPDATES) as u32);
+            assert_eq!(clock_seq(index), expected, "clock {index}");
+        }
+
+        let writer = VdsoWriteGuard::acquire();
+        assert!(VdsoWriteGuard::try_acquire().is_none());
+        drop(writer);
+        assert!(VdsoWriteGuard::try_acquire().is_some());
+    }
+
+    #[test]
+    fn monotonic_base_round_trips_and_is_never_torn() {
+        use std::sync::atomic::AtomicBool;
+
+        let _globals = crate::lock_globals();
+        let tk = || unsafe { &mut (*core::ptr::addr_of_mut!(VDSO_DATA)).time_data.clock_data[0] };
+        let ts = VdsoTimestamp { sec: 42, nsec: 7 };
+        {
+            let _writer = VdsoWriteGuard::acquire();
+            tk().set_monotonic_base(ts);
+        }
+        assert_eq!(vdso_time_data().timekeeper().monotonic_base(), ts);
+
+        // Every value written from here on has `sec == nsec`; a torn read
+        // would not.
+        let write = |v: u64| {
+            let _writer = VdsoWriteGuard::acquire();
+            tk().set_monotonic_base(VdsoTimestamp { sec: v, nsec: v });
+        };
+        write(0);
+        let done = AtomicBool::new(false);
+        std::thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(|| {
+                    while !done.load(Ordering::Relaxed) {
+                        let ts = vdso_time_data().timekeeper().monotonic_base();
+                        assert_eq!(ts.sec, ts.nsec, "torn monotonic base");
+                    }
+                });
+            }
+            for i in 1..20_000u64 {
+                write(i.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+            }
+            done.store(true, Ordering::Relaxed);
+        });
+        update_vdso_data();
+    }
+
+    #[test]
+    fn time_and_timezone_are_read_as_a_pair() {
+        use std::sync::atomic::AtomicBool;
+
+        // Readers interpolate past the published time by at most the host's
+        // counter value, which stays well below the gap between the two.
+        const EARLY_SEC: u64 = 1_000_000_000;
+        const LATE_SEC: u64 = 15_000_000_000;
+        const SPLIT_SEC: u64 = 8_000_000_000;
+        let _globals = crate::lock_globals();
+        set_time_and_timezone(EARLY_SEC * NANOS_PER_SEC, 60, 0);
+        let done = AtomicBool::new(false);
+        std::thread::scope(|s| {
+            for _ in 0..2 {
+                s.spawn(|| {
+                    while !done.load(Ordering::Relaxed) {
+                        // A reader may give up on the busy writer, but
+                        // never returns a mixed pair.
+                        let Some((sec, _, minuteswest, dsttime)) = vdso_gettimeofday() else {
+                            continue;
+                        };
+                        let expected = if sec < SPLIT_SEC { (60, 0) } else { (-120, 1) };
+                        assert_eq!((minuteswest, dsttime), expected, "at {sec} s");
+                    }
+                });
+            }
+            for i in 0..10_000 {
+                if i % 2 == 0 {
+                    set_time_and_timezone(LATE_SEC * NANOS_PER_SEC, -120, 1);
+                } else {
+                    set_time_and_timezone(EARLY_SEC * NANOS_PER_SEC, 60, 0);
+                }
+            }
+            done.store(true, Ordering::Relaxed);
+        });
+        set_time_and_timezone(0, 0, 0);
+    }
+
+    #[test]
+    fn now_pair_differs_by_the_published_offset() {
+        use crate::vdso_time_data::{clocks_calc_mult_shift, timestamp_from_ns};
+
+        let _globals = crate::lock_globals();
+        let mono_ns = 5 * NANOS_PER_SEC + 7;
+        let offset_ns = 1_700_000_000 * NANOS_PER_SEC + 123_456_789;
+        {
+            let _writer = VdsoWriteGuard::acquire();
+            let tk = unsafe { &mut (*core::ptr::addr_of_mut!(VDSO_DATA)).time_data.clock_data[0] };
+            tk.write_seqcount_begin();
+            tk.clock_mode = COUNTER_MODE as i32;
+            let (mult, shift) = clocks_calc_mult_shift(2_997_000_000, NANOS_PER_SEC, 600);
+            tk.set_scaling(mult, shift);
+            tk.cycle_last
+                .store(read_hw_counter_fenced(), Ordering::Relaxed);
+            tk.time_data[ClockBase::Monotonic as usize] = timestamp_from_ns(mono_ns, tk.shift);
+            tk.time_data[ClockBase::Realtime as usize] =
+                timestamp_from_ns(mono_ns + offset_ns, tk.shift);
+            tk.write_seqcount_end();
+        }
+        let ns = |ts: VdsoTimestamp| ts.sec * NANOS_PER_SEC + ts.nsec;
+        let mut prev = mono_ns;
+        for _ in 0..1000 {
+            let (real, mono) = vdso_now_pair().unwrap();
+            assert_eq!(ns(real) - ns(mono), offset_ns);
+            assert!(ns(mono) >= prev);
+            prev = ns(mono);
+        }
+        {
+            let _writer = VdsoWriteGuard::acquire();
+            unsafe {
+                (*core::ptr::addr_of_mut!(VDSO_DATA))
+                    .time_data
+                    .reset_cycle_last()
+            };
+        }
+        update_vdso_data();
+    }
+
+    #[test]
+    fn seqlock_readers_never_see_a_torn_clock() {
+        use std::sync::atomic::AtomicBool;
+
+        const WRITES: u64 = 50_000;
+        let _globals = crate::lock_globals();
+        // Store one value in every field the readers check.
+        let write = |v: u64| {
+            let _writer = VdsoWriteGuard::acquire();
+            let clk = unsafe { &mut (*core::ptr::addr_of_mut!(VDSO_DATA)).time_data.clock_data[1] };
+            clk.write_seqcount_begin();
+            clk.cycle_last.store(v, Ordering::Relaxed);
+            clk.mult = v as u32;
+            clk.time_data = [VdsoTimestamp { sec: v, nsec: v }; _];
+            clk.write_seqcount_end();
+        };
+        write(0);
+        let done = AtomicBool::new(false);
+        std::thread::scope(|s| {
+            for _ in 0..3 {
+                s.spawn(|| {
+                    let clk = vdso_time_data().clock(ClockSource::Raw);
+                    loop {
+                        let (cycle_last, mult, bases) = clk.read(|clk| {
+                            (
+                                clk.cycle_last.load(Ordering::Relaxed),
+                                clk.mult,
+                                clk.time_data,
+                            )
+                        });
+                        assert_eq!(mult, cycle_last as u32);
+                        for ts in bases {
+                            assert_eq!((ts.sec, ts.nsec), (cycle_last, cycle_last));
+                        }
+                        if done.load(Ordering::Relaxed) {
+                            break;
+                        }
+                    }
+                });
+            }
+            for i in 1..=WRITES {
+                write(i.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+            }
+            done.store(true, Ordering::Relaxed);
+        });
+        {
+            let _writer = VdsoWriteGuard::acquire();
+            unsafe {
+                (*core::ptr::addr_of_mut!(VDSO_DATA))
+                    .time_data
+                    .reset_cycle_last()
+            };
+        }
+        update_vdso_data();
+    }
+
+    #[test]
+    fn reader_emulation_bails_out_on_a_stuck_writer() {
+        let _globals = crate::lock_globals();
+        for source in [ClockSource::HresCoarse, ClockSource::Raw] {
+            let clk = vdso_time_data().clock(source);
+            clk.write_seqcount_begin();
+            let base = match source {
+                ClockSource::HresCoarse => ClockBase::Monotonic,
+                ClockSource::Raw => ClockBase::MonotonicRaw,
+            };
+            assert!(vdso_clock_gettime(base as i32).is_none(), "{source:?}");
+            assert!(dump_all_clocks().is_none(), "{source:?}");
+            if source == ClockSource::HresCoarse {
+                assert!(vdso_gettimeofday().is_none());
+                assert!(vdso_now_pair().is_none());
+            }
+            clk.write_seqcount_end();
+        }
+        assert!(vdso_clock_gettime(ClockBase::Monotonic as i32).is_some());
+        assert!(vdso_gettimeofday().is_some());
+        assert!(vdso_now_pair().is_some());
+        assert!(dump_all_clocks().is_some());
+    }
+
+    #[test]
+    fn capabilities_follow_the_bundled_image_and_clock_mode() {
+        let _globals = crate::lock_globals();
+        let set_mode = |mode| unsafe {
+            (*core::ptr::addr_of_mut!(VDSO_DATA))
+                .time_data
+                .set_clock_mode(mode)
+        };
+        let time_caps = VDSO_CAP_CLOCK_GETTIME | VDSO_CAP_GETTIMEOFDAY | VDSO_CAP_TIME;
+        let mode = ClockMode::from_raw(current_clock_mode()).unwrap();
+        set_mode(COUNTER_MODE);
+        assert_eq!(vdso_capabilities(), time_caps);
+        set_mode(ClockMode::None);
+        assert_eq!(vdso_capabilities(), VDSO_CAP_TIME);
+        set_mode(mode);
+        assert_eq!(vdso_capabilities_of(&[]), 0);
+    }
 }
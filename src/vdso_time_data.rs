@@ -1,16 +1,282 @@
-use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, AtomicI64, AtomicU32, AtomicU64, Ordering},
+};
 
+use axerrno::{AxError, AxResult};
 use axplat::time::{
     NANOS_PER_SEC, current_ticks, monotonic_time_nanos, nanos_to_ticks, wall_time_nanos,
 };
 
-const VDSO_BASES: usize = 12;
+use crate::config::{COUNTER_MASK, ClockMode, VDSO_BASES};
+
+/// Number of times a seqlock reader had to retry because of a writer.
+static SEQLOCK_READ_RETRIES: AtomicU64 = AtomicU64::new(0);
+
+/// Get the number of seqlock reader retries since boot.
+pub fn seqlock_read_retries() -> u64 {
+    SEQLOCK_READ_RETRIES.load(Ordering::Relaxed)
+}
+
+/// Number of updates skipped because the counter went backwards.
+static CYCLE_BACKWARDS_EVENTS: AtomicU64 = AtomicU64::new(0);
+
+/// Get the number of updates skipped because the counter went backwards.
+pub fn cycle_backwards_events() -> u64 {
+    CYCLE_BACKWARDS_EVENTS.load(Ordering::Relaxed)
+}
+
+/// Consecutive updates after which a counter still behind the timekeeper's
+/// `cycle_last` is taken to have restarted.
+const MAX_BACKWARDS_UPDATES: u32 = 8;
+/// Consecutive updates that found the counter behind the timekeeper's
+/// `cycle_last`.
+static BACKWARDS_UPDATES: AtomicU32 = AtomicU32::new(0);
+
+/// Number of per-update recalibrations rejected as implausible.
+static RECALIBRATION_REJECTS: AtomicU64 = AtomicU64::new(0);
+
+/// Get the number of per-update recalibrations rejected because they implied
+/// a counter frequency more than twice off the previous scaling.
+pub fn recalibration_rejects() -> u64 {
+    RECALIBRATION_REJECTS.load(Ordering::Relaxed)
+}
+
+/// Consecutive updates after which a platform monotonic time that did not
+/// advance while the counter did is reported as stuck.
+const STUCK_MONO_UPDATES: u32 = 8;
+/// Monotonic time the platform returned to the last update.
+static LAST_PLATFORM_MONO_NS: AtomicU64 = AtomicU64::new(0);
+/// Consecutive updates that saw the same platform monotonic time while the
+/// counter advanced.
+static STUCK_MONO_COUNT: AtomicU32 = AtomicU32::new(0);
+
+/// Largest change of the wall-minus-monotonic offset between two updates that
+/// is treated as sampling jitter rather than a clock step.
+const WALL_OFFSET_JITTER_NS: u64 = 10_000;
+/// Smoothed wall-minus-monotonic offset, `i64::MIN` until the first update.
+static WALL_TO_MONO_NS: AtomicI64 = AtomicI64::new(i64::MIN);
+/// Offset samples of the last three updates, newest first.
+static WALL_TO_MONO_SAMPLES: [AtomicI64; 3] = [const { AtomicI64::new(i64::MIN) }; 3];
+
+/// Make `offset` the wall-to-monotonic offset and the whole sample history,
+/// or forget both with `i64::MIN`.
+fn reset_wall_offset(offset: i64) {
+    for slot in &WALL_TO_MONO_SAMPLES {
+        slot.store(offset, Ordering::Relaxed);
+    }
+    WALL_TO_MONO_NS.store(offset, Ordering::Relaxed);
+}
+
+/// Derive realtime from `mono_ns` plus a smoothed wall-to-monotonic offset.
+///
+/// `wall_ns` and `mono_ns` are sampled at slightly different instants, so
+/// their difference jitters between updates. The offset published is the
+/// median of the samples of the last three updates, which drops a single
+/// outlier entirely and does not round. A wall clock slewing against
+/// monotonic is followed exactly one update behind, lagging by the drift of
+/// one update interval. A change larger than `WALL_OFFSET_JITTER_NS` is taken
+/// as a clock step, applied at once and restarting the history.
+fn smoothed_wall_ns(wall_ns: u64, mono_ns: u64) -> u64 {
+    let sample = (wall_ns as i64).wrapping_sub(mono_ns as i64);
+    let prev = WALL_TO_MONO_NS.load(Ordering::Relaxed);
+    let offset = if prev == i64::MIN || sample.abs_diff(prev) > WALL_OFFSET_JITTER_NS {
+        reset_wall_offset(sample);
+        sample
+    } else {
+        let [newest, middle, oldest] = &WALL_TO_MONO_SAMPLES;
+        oldest.store(middle.load(Ordering::Relaxed), Ordering::Relaxed);
+        middle.store(newest.load(Ordering::Relaxed), Ordering::Relaxed);
+        newest.store(sample, Ordering::Relaxed);
+        let mut samples = WALL_TO_MONO_SAMPLES
+            .each_ref()
+            .map(|s| s.load(Ordering::Relaxed));
+        samples.sort_unstable();
+        samples[1]
+    };
+    WALL_TO_MONO_NS.store(offset, Ordering::Relaxed);
+    mono_ns.wrapping_add_signed(offset)
+}
+
+/// Total time spent in suspend, the offset of boottime from monotonic.
+static SUSPENDED_NS: AtomicU64 = AtomicU64::new(0);
+
+/// Account `suspend_ns` of suspend time in boottime and make the next update
+/// adopt the wall clock without smoothing, as it was re-read from the RTC.
+pub fn account_suspend(suspend_ns: u64) {
+    SUSPENDED_NS.fetch_add(suspend_ns, Ordering::Relaxed);
+    reset_wall_offset(i64::MIN);
+}
+
+/// Upper bound of the spin count between two seqlock read attempts.
+const MAX_READ_BACKOFF: u32 = 64;
+
+/// Relax the CPU for `backoff` spins before the next seqlock read attempt,
+/// doubling the wait up to [`MAX_READ_BACKOFF`].
+fn seqlock_backoff(backoff: &mut u32) {
+    for _ in 0..*backoff {
+        core::hint::spin_loop();
+    }
+    *backoff = (*backoff * 2).min(MAX_READ_BACKOFF);
+}
+
+/// Whether a leap-second smear is in progress.
+static LEAP_SMEAR_ACTIVE: AtomicBool = AtomicBool::new(false);
+/// Realtime rate bias during the smear, in parts per billion.
+static LEAP_SMEAR_RATE_PPB: AtomicI64 = AtomicI64::new(0);
+/// Monotonic time at which the smear started.
+static LEAP_SMEAR_START_NS: AtomicU64 = AtomicU64::new(0);
+/// Smear offset applied to realtime by the last update, 0 without a smear.
+static LEAP_SMEAR_LAST_NS: AtomicI64 = AtomicI64::new(0);
+
+/// Record the leap-second smear state used by [`VdsoTimeData::update`].
+pub fn set_leap_smear(active: bool, rate_ppb: i64) {
+    set_leap_smear_at(active, rate_ppb, monotonic_time_nanos());
+}
+
+/// Like [`set_leap_smear`], for a smear starting at monotonic time
+/// `start_ns`.
+fn set_leap_smear_at(active: bool, rate_ppb: i64, start_ns: u64) {
+    if active {
+        LEAP_SMEAR_RATE_PPB.store(rate_ppb, Ordering::Relaxed);
+        LEAP_SMEAR_START_NS.store(start_ns, Ordering::Relaxed);
+    }
+    LEAP_SMEAR_ACTIVE.store(active, Ordering::Release);
+}
+
+/// Rate bias of the active leap-second smear in parts per billion, or
+/// `None` without one.
+fn leap_smear_rate_ppb() -> Option<i64> {
+    LEAP_SMEAR_ACTIVE
+        .load(Ordering::Acquire)
+        .then(|| LEAP_SMEAR_RATE_PPB.load(Ordering::Relaxed))
+}
+
+/// Offset the active leap-second smear, if any, adds to realtime at
+/// monotonic time `mono_ns`.
+fn leap_smear_ns(mono_ns: u64) -> i64 {
+    let Some(rate) = leap_smear_rate_ppb() else {
+        return 0;
+    };
+    let elapsed = mono_ns.saturating_sub(LEAP_SMEAR_START_NS.load(Ordering::Relaxed));
+    let offset = elapsed as i128 * rate as i128 / NANOS_PER_SEC as i128;
+    offset.clamp(i64::MIN as i128, i64::MAX as i128) as i64
+}
+
+/// Scaling that makes the timekeeper interpolate realtime at the rate of a
+/// smear slowing it down, or `None` if the unbiased `mult_shift` is kept.
+///
+/// Userspace reads every base of the timekeeper with one `mult`, so the
+/// bias slows monotonic and boottime down between updates as well. Each
+/// update re-anchors them to the platform monotonic time, a forward step of
+/// at most the rate times the update interval, while realtime stays
+/// continuous. A smear speeding realtime up cannot be interpolated that way,
+/// as monotonic would then step back at every update; its realtime steps
+/// forward at each update instead.
+fn leap_smear_mult_shift(ticks_per_sec: u64, maxsec: u32) -> Option<(u32, u32)> {
+    let rate = leap_smear_rate_ppb().filter(|rate| *rate < 0)?;
+    let smeared_ns_per_sec = NANOS_PER_SEC
+        .checked_add_signed(rate)
+        .filter(|ns| *ns > 0)?;
+    let from = if identity_scaling() {
+        NANOS_PER_SEC
+    } else {
+        ticks_per_sec
+    };
+    Some(clocks_calc_mult_shift(from, smeared_ns_per_sec, maxsec))
+        .filter(|mult_shift| *mult_shift != FALLBACK_MULT_SHIFT)
+}
+
+/// Counter interval, in seconds, that `mult`/`shift` must convert without
+/// overflowing, see [`set_max_conversion_secs`].
+static MAX_CONVERSION_SECS: AtomicU32 = AtomicU32::new(10);
+
+/// Set the `maxsec` argument [`VdsoTimeData::update`] passes to
+/// [`clocks_calc_mult_shift`], at least 1.
+///
+/// A smaller value allows a larger `shift` and so a more precise conversion,
+/// but the reader's `delta * mult` then overflows sooner, so updates must be
+/// at least that frequent. Takes effect with the next update.
+pub fn set_max_conversion_secs(secs: u32) {
+    MAX_CONVERSION_SECS.store(secs.max(1), Ordering::Relaxed);
+}
+
+/// Counter frequency chosen at init, see [`set_counter_hz`]; 0 to use the
+/// platform's.
+static COUNTER_HZ: AtomicU64 = AtomicU64::new(0);
+
+/// Derive `mult`/`shift` from a counter frequency of `hz` instead of the one
+/// the platform reports, or go back to the platform's with 0. Takes effect
+/// with the next update.
+pub fn set_counter_hz(hz: u64) {
+    COUNTER_HZ.store(hz, Ordering::Relaxed);
+}
+
+/// Get the counter frequency updates derive `mult`/`shift` from, in Hz: the
+/// one given to [`set_counter_hz`], or else the platform's.
+pub fn counter_hz() -> u64 {
+    match COUNTER_HZ.load(Ordering::Relaxed) {
+        0 => nanos_to_ticks(NANOS_PER_SEC),
+        hz => hz,
+    }
+}
+
+/// Choose the counter frequency `mult`/`shift` are derived from, given the
+/// one `detected` by the CPU (e.g. `CNTFRQ_EL0`) and the one `configured` by
+/// the platform.
+///
+/// The platform value is authoritative whenever it is known, since every CPU
+/// shares it; a detected value that disagrees points at firmware that
+/// programmed cores differently and is only logged. The detected value is
+/// used only when the platform reports none (0).
+pub fn authoritative_counter_hz(detected: u64, configured: u64) -> u64 {
+    if configured == 0 {
+        return detected;
+    }
+    if detected != configured {
+        log::warn!(
+            "CPU reports a {} Hz counter but the platform counter runs at {} Hz",
+            detected,
+            configured
+        );
+    }
+    configured
+}
+
+/// Set when the counter is taken to count nanoseconds, see
+/// [`set_identity_scaling`].
+static IDENTITY_SCALING: AtomicBool = AtomicBool::new(false);
 
-use crate::config::ClockMode;
+/// Treat the counter as a full 64-bit nanosecond clock, as some emulators
+/// provide, or go back to deriving the scaling from its frequency.
+///
+/// While set, updates publish `mult = 1`, `shift = 0` and `mask = u64::MAX`
+/// instead of the [`clocks_calc_mult_shift`] result, so the reader adds the
+/// counter delta to the base unchanged. Takes effect with the next update.
+pub fn set_identity_scaling(enabled: bool) {
+    IDENTITY_SCALING.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether identity scaling is forced, see [`set_identity_scaling`].
+pub fn identity_scaling() -> bool {
+    IDENTITY_SCALING.load(Ordering::Relaxed)
+}
+
+/// Set while the published clocks are frozen for deterministic replay.
+static VDSO_FROZEN: AtomicBool = AtomicBool::new(false);
+
+/// Whether the published clocks are frozen, see [`VdsoTimeData::freeze`].
+pub fn is_frozen() -> bool {
+    VDSO_FROZEN.load(Ordering::Acquire)
+}
 
 /// vDSO timestamp structure
+///
+/// For the high resolution bases, `nsec` is a fixed-point value holding
+/// nanoseconds shifted left by the owning clock's `shift`, so that the reader
+/// can add `delta_cycles * mult` before shifting back down.
 #[repr(C)]
-#[derive(Clone, Copy, Default)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
 pub struct VdsoTimestamp {
     /// Seconds
     pub sec: u64,
@@ -25,6 +291,85 @@ impl VdsoTimestamp {
     }
 }
 
+/// Scaling [`clocks_calc_mult_shift`] returns when it has no conversion,
+/// e.g. for an unknown (zero) counter frequency. An update passed it keeps
+/// the clock's scaling, or derives one from the counter and monotonic deltas
+/// between updates.
+pub const FALLBACK_MULT_SHIFT: (u32, u32) = (u32::MAX, 0);
+
+/// Largest `shift` a [`VdsoClock`] may publish: the largest for which the
+/// sub-second part `(NANOS_PER_SEC - 1) << shift` of a base still fits in
+/// `u64` (34).
+pub const MAX_SHIFT: u32 = (u64::MAX / (NANOS_PER_SEC - 1)).ilog2();
+
+/// Indices into [`VdsoClock::time_data`], equal to the Linux clock ids.
+///
+/// Linux defines no coarse boottime clock: the coarse variants are only
+/// `CLOCK_REALTIME_COARSE` (5) and `CLOCK_MONOTONIC_COARSE` (6), and
+/// `CLOCK_BOOTTIME` (7) is always interpolated, so there is no slot for one.
+///
+/// As in Linux, the coarse bases hold plain (unshifted) nanoseconds and are
+/// returned without interpolation, see [`Self::is_coarse`]. Userspace reads
+/// `CLOCK_MONOTONIC_RAW` (4) from the raw clock only, see [`Self::source`].
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockBase {
+    Realtime        = 0,
+    Monotonic       = 1,
+    MonotonicRaw    = 4,
+    RealtimeCoarse  = 5,
+    MonotonicCoarse = 6,
+    Boottime        = 7,
+}
+
+const _: () = assert!((ClockBase::Boottime as usize) < VDSO_BASES);
+
+impl ClockBase {
+    /// All bases maintained by the update.
+    pub const ALL: [Self; 6] = [
+        Self::Realtime,
+        Self::Monotonic,
+        Self::MonotonicRaw,
+        Self::RealtimeCoarse,
+        Self::MonotonicCoarse,
+        Self::Boottime,
+    ];
+
+    /// Map a `clockid_t` to the base maintained for it, if any.
+    pub const fn from_clock_id(clock_id: i32) -> Option<Self> {
+        match clock_id {
+            0 => Some(Self::Realtime),
+            1 => Some(Self::Monotonic),
+            4 => Some(Self::MonotonicRaw),
+            5 => Some(Self::RealtimeCoarse),
+            6 => Some(Self::MonotonicCoarse),
+            7 => Some(Self::Boottime),
+            _ => None,
+        }
+    }
+
+    /// Clock the userspace vDSO reads this base from: the raw clock for
+    /// `CLOCK_MONOTONIC_RAW`, the timekeeper for everything else.
+    pub const fn source(self) -> ClockSource {
+        match self {
+            Self::MonotonicRaw => ClockSource::Raw,
+            _ => ClockSource::HresCoarse,
+        }
+    }
+
+    /// Whether the base is a coarse one, published as plain nanoseconds at
+    /// the last update and read without the counter.
+    pub const fn is_coarse(self) -> bool {
+        matches!(self, Self::RealtimeCoarse | Self::MonotonicCoarse)
+    }
+}
+
+/// Linux `struct vdso_clock`: clock bases and the counter scaling used to
+/// interpolate them, under a seqlock.
+///
+/// This is the only definition. Every arch's `VdsoData` embeds it through
+/// [`VdsoTimeData`], and each arch's `vdso_data` module asserts its size
+/// against the layout its bundled vDSO reads.
 #[repr(C)]
 pub struct VdsoClock {
     pub seq: AtomicU32,
@@ -36,7 +381,14 @@ pub struct VdsoClock {
     pub mult: u32,
     pub shift: u32,
     pub time_data: [VdsoTimestamp; VDSO_BASES],
-    pub _unused: u32,
+    /// Number of times the masked counter wrapped, counted by the update.
+    ///
+    /// Together with the counter it forms a wider counter that survives
+    /// wraps, see [`Self::extended_cycles`]. A wrap is only seen if an update
+    /// runs at least once per wrap period (`mask + 1` ticks). Counted when
+    /// `cycle_last` advances and cleared with it. Occupies the word Linux
+    /// leaves unused, so the bundled userspace vDSO ignores it.
+    pub counter_wraps: u32,
 }
 
 impl VdsoClock {
@@ -50,35 +402,356 @@ impl VdsoClock {
             #[cfg(target_arch = "x86_64")]
             max_cycles: 0,
 
-            mask: u64::MAX,
+            mask: COUNTER_MASK,
             mult: 0,
             shift: 32,
             time_data: [VdsoTimestamp::new(); VDSO_BASES],
-            _unused: 0,
+            counter_wraps: 0,
+        }
+    }
+
+    /// Whether the clock carries a real counter scaling, as opposed to none
+    /// (`mult == 0`) or the [`FALLBACK_MULT_SHIFT`] placeholder.
+    pub fn is_calibrated(&self) -> bool {
+        self.mult != 0 && (self.mult, self.shift) != FALLBACK_MULT_SHIFT
+    }
+
+    /// Publish a new `mult`/`shift` pair.
+    ///
+    /// A `shift` above [`MAX_SHIFT`] would overflow the `nsec << shift`
+    /// storage, so it is capped and `mult` scaled down by the same power of
+    /// two, keeping the ratio at slightly lower precision.
+    pub fn set_scaling(&mut self, mult: u32, shift: u32) {
+        let excess = shift.saturating_sub(MAX_SHIFT);
+        self.mult = mult.checked_shr(excess).unwrap_or(0);
+        self.shift = shift - excess;
+    }
+
+    /// Switch to `mode` inside the seqlock write section.
+    ///
+    /// If the mode changes, `cycle_last` and the scaling are dropped too, so
+    /// the next update takes the initialization path instead of scaling a
+    /// delta measured in the old mode.
+    pub fn set_clock_mode(&mut self, mode: ClockMode) {
+        if self.clock_mode != mode as i32 {
+            self.write_seqcount_begin();
+            self.clock_mode = mode as i32;
+            self.clear_cycle_last();
+            self.mult = 0;
+            self.write_seqcount_end();
+        }
+    }
+
+    /// Make `cycle_now` the counter value the published bases correspond
+    /// to, counting a wrap of the masked counter since the previous one.
+    fn advance_cycle_last(&mut self, cycle_now: u64) {
+        let prev = self.cycle_last.load(Ordering::Relaxed);
+        if prev != 0 && (cycle_now & self.mask) < (prev & self.mask) {
+            self.counter_wraps = self.counter_wraps.wrapping_add(1);
+        }
+        self.cycle_last.store(cycle_now, Ordering::Relaxed);
+    }
+
+    /// Forget `cycle_last`, and the wraps counted against it, so that the
+    /// next update takes the initialization path.
+    fn clear_cycle_last(&mut self) {
+        self.cycle_last.store(0, Ordering::Relaxed);
+        self.counter_wraps = 0;
+    }
+
+    /// Adopt the bases and counter state of `stashed`, keeping the live
+    /// sequence count and clock mode. Callers must hold the seqlock write
+    /// section.
+    ///
+    /// A clock stashed in another mode, or one this build does not know,
+    /// keeps its bases but drops `cycle_last` and the scaling as on a mode
+    /// switch, so the next update re-initializes them. A scaling whose
+    /// `shift` the update could never have published is not a stashed clock
+    /// at all, and the live clock is kept as is.
+    fn restore(&mut self, stashed: &VdsoClock) {
+        if stashed.shift > MAX_SHIFT {
+            return;
+        }
+        self.cycle_last.store(
+            stashed.cycle_last.load(Ordering::Relaxed),
+            Ordering::Relaxed,
+        );
+        #[cfg(target_arch = "x86_64")]
+        {
+            self.max_cycles = stashed.max_cycles;
+        }
+        self.mask = stashed.mask;
+        self.mult = stashed.mult;
+        self.shift = stashed.shift;
+        self.time_data = stashed.time_data;
+        self.counter_wraps = stashed.counter_wraps;
+        let same_mode =
+            ClockMode::from_raw(stashed.clock_mode).is_some_and(|m| m as i32 == self.clock_mode);
+        if !same_mode {
+            self.clear_cycle_last();
+            self.mult = 0;
+        }
+    }
+
+    /// Overwrite the monotonic base inside a seqlock write section.
+    ///
+    /// Low-level API for tests and calibration: `ts.nsec` must already be
+    /// shifted by `self.shift`, and the caller must be the only writer (e.g.
+    /// hold a `VdsoWriteGuard`). The next update replaces the value.
+    pub fn set_monotonic_base(&mut self, ts: VdsoTimestamp) {
+        self.write_seqcount_begin();
+        self.time_data[ClockBase::Monotonic as usize] = ts;
+        self.write_seqcount_end();
+    }
+
+    /// Read the published monotonic base (with its shifted `nsec`) under the
+    /// seqlock.
+    pub fn monotonic_base(&self) -> VdsoTimestamp {
+        self.read(|clk| clk.time_data[ClockBase::Monotonic as usize])
+    }
+
+    /// Reconstruct the time of `base` at counter value `cycles` the way the
+    /// userspace vDSO does, returning plain (unshifted) nanoseconds in `nsec`.
+    ///
+    /// The published base holds `nsec << shift`; the elapsed cycles are
+    /// scaled into the same fixed-point domain and the sum is shifted down:
+    ///
+    /// `ns = (base.nsec + ((cycles - cycle_last) & mask) * mult) >> shift`
+    ///
+    /// Whole seconds carried out of `ns` are added to `base.sec`, which is
+    /// never multiplied by `NANOS_PER_SEC`, so the full `u64` second range is
+    /// representable; the sum saturates instead of wrapping. Coarse bases are
+    /// returned as published, ignoring `cycles`. Callers must hold a seqlock
+    /// read section, see [`Self::read`].
+    pub fn hres_time(&self, base: ClockBase, cycles: u64) -> VdsoTimestamp {
+        let ts = self.time_data[base as usize];
+        if base.is_coarse() {
+            return ts;
+        }
+        let delta = self.delta_cycles(cycles);
+        let ns = (ts.nsec as u128 + delta as u128 * self.mult as u128) >> self.shift;
+        let carry = u64::try_from(ns / NANOS_PER_SEC as u128).unwrap_or(u64::MAX);
+        VdsoTimestamp {
+            sec: ts.sec.saturating_add(carry),
+            nsec: (ns % NANOS_PER_SEC as u128) as u64,
+        }
+    }
+
+    /// Nanoseconds elapsed between the last update and counter value
+    /// `cycles`, using the published scaling. Always 0 without a counter.
+    pub fn ns_since_update(&self, cycles: u64) -> u64 {
+        self.cycles_to_ns(self.delta_cycles(cycles))
+    }
+
+    /// Masked counter delta from `cycle_last` to `cycles`, or 0 if `cycles`
+    /// is behind `cycle_last`.
+    ///
+    /// A delta in the upper half of the counter range (above `mask >> 1`) is
+    /// not a forward step but a read slightly behind `cycle_last`, e.g. on a
+    /// CPU whose counter lags the one that ran the update. Like the kernel's
+    /// `clocksource_delta`, it is clamped to 0 so the reader returns the base
+    /// time instead of one almost a full counter period ahead. Updates run
+    /// well within half the range, see `max_update_interval_ns`.
+    pub fn delta_cycles(&self, cycles: u64) -> u64 {
+        let delta = cycles.wrapping_sub(self.cycle_last.load(Ordering::Relaxed)) & self.mask;
+        if delta > self.mask >> 1 { 0 } else { delta }
+    }
+
+    /// Convert a counter delta to nanoseconds with the published `mask`,
+    /// `mult` and `shift`, truncating like the reader.
+    pub fn cycles_to_ns(&self, cycles: u64) -> u64 {
+        (((cycles & self.mask) as u128 * self.mult as u128) >> self.shift) as u64
+    }
+
+    /// Extend the counter value `cycles` by the wraps counted so far,
+    /// including a wrap since the last update if `cycles` is below
+    /// `cycle_last`. Callers must hold a seqlock read section.
+    pub fn extended_cycles(&self, cycles: u64) -> u128 {
+        let width = self.mask.count_ones();
+        let last = self.cycle_last.load(Ordering::Relaxed) & self.mask;
+        let now = cycles & self.mask;
+        let wraps = self.counter_wraps as u128 + (now < last) as u128;
+        (wraps << width) | now as u128
+    }
+
+    /// Wait for an even sequence count and return it.
+    pub fn read_seqcount_begin(&self) -> u32 {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq & 1 == 0 {
+                return seq;
+            }
+            core::hint::spin_loop();
         }
     }
 
+    /// Check whether a writer ran since [`Self::read_seqcount_begin`]
+    /// returned `start`.
+    pub fn read_seqcount_retry(&self, start: u32) -> bool {
+        core::sync::atomic::fence(Ordering::Acquire);
+        self.seq.load(Ordering::Relaxed) != start
+    }
+
+    /// Run `f` on a consistent snapshot of this clock, retrying until no
+    /// writer interfered.
+    pub fn read<T>(&self, f: impl Fn(&Self) -> T) -> T {
+        let mut backoff = 1;
+        loop {
+            let seq = self.read_seqcount_begin();
+            let val = f(self);
+            if !self.read_seqcount_retry(seq) {
+                return val;
+            }
+            SEQLOCK_READ_RETRIES.fetch_add(1, Ordering::Relaxed);
+            seqlock_backoff(&mut backoff);
+        }
+    }
+
+    /// Like [`Self::read`], but give up with [`AxError::TimedOut`] once
+    /// `max_retries` attempts in total were disturbed by a writer, so a stuck
+    /// writer (odd sequence count) cannot hang the reader. Zero makes no
+    /// attempt at all.
+    pub fn try_read<T>(&self, max_retries: u32, f: impl Fn(&Self) -> T) -> AxResult<T> {
+        let mut backoff = 1;
+        for _ in 0..max_retries {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq & 1 == 0 {
+                let val = f(self);
+                if !self.read_seqcount_retry(seq) {
+                    return Ok(val);
+                }
+            }
+            SEQLOCK_READ_RETRIES.fetch_add(1, Ordering::Relaxed);
+            seqlock_backoff(&mut backoff);
+        }
+        Err(AxError::TimedOut)
+    }
+
+    /// Make the sequence count odd before the clock is written.
+    ///
+    /// Like Linux `write_seqcount_begin`, the increment is followed by a
+    /// write barrier (`smp_wmb`), here a release fence: it keeps the data
+    /// writes from becoming visible before the odd count, so a reader that
+    /// saw any of them also sees the count change at its retry check.
     pub fn write_seqcount_begin(&self) {
         let seq = self.seq.load(Ordering::Relaxed);
-        self.seq.store(seq.wrapping_add(1), Ordering::Release);
-        core::sync::atomic::fence(Ordering::SeqCst);
+        self.seq.store(seq.wrapping_add(1), Ordering::Relaxed);
+        core::sync::atomic::fence(Ordering::Release);
     }
 
+    /// Make the sequence count even again after the clock was written. The
+    /// release store keeps the data writes from moving past it, so a reader
+    /// that acquires the new count sees all of them.
     pub fn write_seqcount_end(&self) {
-        core::sync::atomic::fence(Ordering::SeqCst);
         let seq = self.seq.load(Ordering::Relaxed);
         self.seq.store(seq.wrapping_add(1), Ordering::Release);
     }
 }
 
+impl fmt::Debug for VdsoClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        struct Mode(i32);
+        impl fmt::Debug for Mode {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                match ClockMode::from_raw(self.0) {
+                    Some(mode) => write!(f, "{mode:?}"),
+                    None => write!(f, "Unknown({})", self.0),
+                }
+            }
+        }
+        struct Bases<'a>(&'a [VdsoTimestamp; VDSO_BASES]);
+        impl fmt::Debug for Bases<'_> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.debug_map()
+                    .entries(
+                        ClockBase::ALL
+                            .iter()
+                            .map(|base| (base, &self.0[*base as usize])),
+                    )
+                    .finish()
+            }
+        }
+
+        f.debug_struct("VdsoClock")
+            .field("seq", &self.seq.load(Ordering::Relaxed))
+            .field("clock_mode", &Mode(self.clock_mode))
+            .field("cycle_last", &self.cycle_last.load(Ordering::Relaxed))
+            .field("mask", &format_args!("{:#x}", self.mask))
+            .field("mult", &self.mult)
+            .field("shift", &self.shift)
+            .field("time_data", &Bases(&self.time_data))
+            .finish()
+    }
+}
+
 #[repr(C)]
 #[repr(align(4096))]
 pub struct VdsoTimeData {
+    /// Room Linux before 6.15 leaves ahead of `_vdso_data` in the x86 VVAR
+    /// page, see `X86_VDSO_CLOCKS_OFFSET`.
+    #[cfg(all(target_arch = "x86_64", feature = "x86-legacy-vvar"))]
+    pub _legacy_pad: [u8; crate::x86_64::config::X86_VDSO_CLOCKS_OFFSET],
     pub clock_data: [VdsoClock; 2],
     pub tz_minuteswest: i32,
     pub tz_dsttime: i32,
     pub hrtimer_res: u32,
-    pub __unused: u32,
+    /// Median cost of one hardware counter read in counter ticks, measured by
+    /// `calibrate_counter_overhead`. Linux leaves this word unused.
+    pub counter_read_overhead: u32,
+    /// Monotonic time of the last update, in nanoseconds.
+    ///
+    /// Lets userspace without a readable counter (`ClockMode::None`)
+    /// interpolate `base + (now - last_update_mono_ns)` from a cheap time
+    /// source of its own. Written in the seqlock section of the timekeeper
+    /// clock.
+    pub last_update_mono_ns: u64,
+    /// Change of the realtime-minus-monotonic offset at the last update, in
+    /// nanoseconds: negative when the wall clock was stepped back. Sampling
+    /// jitter is smoothed out, so this is 0 between steps. Written in the
+    /// seqlock section of the timekeeper clock.
+    pub last_wall_step_ns: i64,
+    /// CPU and NUMA node ids of each CPU, indexed by CPU id and set as the
+    /// CPU comes online, see [`Self::set_cpu_info`].
+    ///
+    /// A `getcpu` fast path must first learn its CPU id from a register the
+    /// kernel programs per CPU (e.g. `TSC_AUX` through `rdpid` on x86_64,
+    /// `CSR.CPUID` on loongarch64) and can then look up its node here. The
+    /// bundled vDSO images do not read this table; their `getcpu` uses those
+    /// registers or the syscall.
+    pub cpu_info: [VdsoCpuInfo; VDSO_MAX_CPUS],
+}
+
+/// Number of CPUs [`VdsoTimeData::cpu_info`] has entries for.
+pub const VDSO_MAX_CPUS: usize = 128;
+
+/// One entry of [`VdsoTimeData::cpu_info`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VdsoCpuInfo {
+    pub cpu: u32,
+    pub node: u32,
+}
+
+impl VdsoCpuInfo {
+    /// Entry of a CPU that has not come online.
+    pub const UNSET: Self = Self {
+        cpu: u32::MAX,
+        node: u32::MAX,
+    };
+}
+
+/// Indices into [`VdsoTimeData::clock_data`], following the Linux
+/// `CS_HRES_COARSE` and `CS_RAW` clock sources.
+///
+/// Time namespaces do not use a second entry here: Linux publishes them in a
+/// separate VVAR page with its own copy of the whole structure.
+#[repr(usize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockSource {
+    /// The timekeeper's clock, serving all clocks except the raw one.
+    HresCoarse = 0,
+    /// The clock serving `CLOCK_MONOTONIC_RAW`.
+    Raw        = 1,
 }
 
 impl Default for VdsoTimeData {
@@ -90,27 +763,416 @@ impl Default for VdsoTimeData {
 impl VdsoTimeData {
     pub const fn new() -> Self {
         Self {
+            #[cfg(all(target_arch = "x86_64", feature = "x86-legacy-vvar"))]
+            _legacy_pad: [0; crate::x86_64::config::X86_VDSO_CLOCKS_OFFSET],
             clock_data: [VdsoClock::new(), VdsoClock::new()],
             tz_minuteswest: 0,
             tz_dsttime: 0,
             hrtimer_res: 1,
-            __unused: 0,
+            counter_read_overhead: 0,
+            last_update_mono_ns: 0,
+            last_wall_step_ns: 0,
+            cpu_info: [VdsoCpuInfo::UNSET; VDSO_MAX_CPUS],
         }
     }
 
-    pub fn update(&mut self) {
-        let cycle_now = current_ticks();
-        let wall_ns = wall_time_nanos();
-        let mono_ns = monotonic_time_nanos();
-        let ticks_per_sec = nanos_to_ticks(NANOS_PER_SEC);
-        let mult_shift = clocks_calc_mult_shift(ticks_per_sec, NANOS_PER_SEC, 10);
+    /// Set the clock mode advertised by the clock for `source` alone.
+    pub const fn with_clock_mode_of(mut self, source: ClockSource, mode: i32) -> Self {
+        self.clock_data[source as usize].clock_mode = mode;
+        self
+    }
 
+    /// Get the clock published for `source`.
+    pub fn clock(&self, source: ClockSource) -> &VdsoClock {
+        &self.clock_data[source as usize]
+    }
+
+    /// Get the timekeeper's clock, which also guards the fields outside the
+    /// clocks such as the timezone.
+    pub fn timekeeper(&self) -> &VdsoClock {
+        self.clock(ClockSource::HresCoarse)
+    }
+
+    /// Set the clock mode advertised by every clock.
+    pub const fn with_clock_mode(mut self, mode: i32) -> Self {
+        let mut i = 0;
+        while i < self.clock_data.len() {
+            self.clock_data[i].clock_mode = mode;
+            i += 1;
+        }
+        self
+    }
+
+    /// Switch every clock to `mode`, see [`VdsoClock::set_clock_mode`].
+    pub fn set_clock_mode(&mut self, mode: ClockMode) {
+        for clk in self.clock_data.iter_mut() {
+            clk.set_clock_mode(mode);
+        }
+    }
+
+    /// Switch the clock for `source` alone to `mode`, leaving the others.
+    pub fn set_clock_mode_of(&mut self, source: ClockSource, mode: ClockMode) {
+        self.clock_data[source as usize].set_clock_mode(mode);
+    }
+
+    /// Publish the CPU and NUMA node ids of `cpu`, returning `InvalidInput`
+    /// if it has no entry in [`Self::cpu_info`].
+    ///
+    /// Entries are written once per CPU and never change afterwards, so
+    /// they are not covered by a seqlock.
+    pub fn set_cpu_info(&mut self, cpu: u32, node: u32) -> AxResult<()> {
+        let entry = self
+            .cpu_info
+            .get_mut(cpu as usize)
+            .ok_or(AxError::InvalidInput)?;
+        unsafe { core::ptr::write_volatile(entry, VdsoCpuInfo { cpu, node }) };
+        Ok(())
+    }
+
+    /// Get the published timezone as `(tz_minuteswest, tz_dsttime)`.
+    pub fn timezone(&self) -> (i32, i32) {
+        unsafe {
+            (
+                core::ptr::read_volatile(&self.tz_minuteswest),
+                core::ptr::read_volatile(&self.tz_dsttime),
+            )
+        }
+    }
+
+    /// Stop every clock at its current time until [`Self::unfreeze`].
+    ///
+    /// Each base is rebased to the time at the current counter value and
+    /// `mult` is zeroed, so readers see a constant time regardless of the
+    /// counter. Updates are no-ops while frozen.
+    pub fn freeze(&mut self) {
+        self.freeze_at(current_ticks());
+    }
+
+    /// Freeze every clock at its time at counter value `cycle_now`, see
+    /// [`Self::freeze`].
+    fn freeze_at(&mut self, cycle_now: u64) {
+        VDSO_FROZEN.store(true, Ordering::Release);
+        for clk in self.clock_data.iter_mut() {
+            clk.write_seqcount_begin();
+            for base in ClockBase::ALL.into_iter().filter(|b| !b.is_coarse()) {
+                let ts = clk.hres_time(base, cycle_now);
+                clk.time_data[base as usize] = VdsoTimestamp {
+                    sec: ts.sec,
+                    nsec: ts.nsec << clk.shift,
+                };
+            }
+            clk.advance_cycle_last(cycle_now);
+            clk.mult = 0;
+            clk.write_seqcount_end();
+        }
+    }
+
+    /// Resume updates after [`Self::freeze`] and publish the current time,
+    /// which jumps forward by the time spent frozen.
+    pub fn unfreeze(&mut self) {
+        self.unfreeze_with(UpdateSample::now());
+    }
+
+    /// Resume updates and publish `sample`, see [`Self::unfreeze`].
+    fn unfreeze_with(&mut self, sample: UpdateSample) {
+        VDSO_FROZEN.store(false, Ordering::Release);
+        self.reset_cycle_last();
+        self.publish(sample, None);
+    }
+
+    /// Forget the last counter value of every clock so that the next update
+    /// takes the initialization path.
+    pub fn reset_cycle_last(&mut self) {
         for clk in self.clock_data.iter_mut() {
             clk.write_seqcount_begin();
-            update_vdso_clock(clk, cycle_now, wall_ns, mono_ns, mult_shift);
+            clk.clear_cycle_last();
             clk.write_seqcount_end();
         }
     }
+
+    pub fn update(&mut self) {
+        self.update_with(None);
+    }
+
+    /// Adopt the clocks and timezone of data stashed before a warm reboot
+    /// that preserved the counter.
+    ///
+    /// Each clock keeps its live sequence count and clock mode. One stashed
+    /// in another mode, or one this build does not know, keeps the stashed
+    /// bases but drops `cycle_last` and the scaling, so the next update
+    /// re-initializes them.
+    ///
+    /// The state later updates build on is resynchronized with the restored
+    /// timekeeper: the wall-to-monotonic offset, the suspend time (boottime
+    /// minus monotonic) and the last platform monotonic time. A freeze or
+    /// leap-second smear in progress is dropped.
+    pub fn restore(&mut self, stashed: &[VdsoClock; 2], tz_minuteswest: i32, tz_dsttime: i32) {
+        for (i, (clk, stashed)) in self.clock_data.iter_mut().zip(stashed).enumerate() {
+            clk.write_seqcount_begin();
+            clk.restore(stashed);
+            if i == 0 {
+                let mono_ns = restore_update_state(clk);
+                self.tz_minuteswest = tz_minuteswest;
+                self.tz_dsttime = tz_dsttime;
+                self.last_update_mono_ns = mono_ns;
+                self.last_wall_step_ns = 0;
+            }
+            clk.write_seqcount_end();
+        }
+    }
+
+    /// Set realtime to `wall_ns` and the timezone in the same seqlock window,
+    /// as `settimeofday` does, so no reader sees one without the other.
+    ///
+    /// `wall_ns` is taken as the time at the counter value sampled here,
+    /// which becomes the new `cycle_last`, and replaces the smoothed
+    /// wall-to-monotonic offset. The platform wall clock must be set to match
+    /// or the next update steps realtime back to it.
+    pub fn set_time_and_timezone(&mut self, wall_ns: u64, tz_minuteswest: i32, tz_dsttime: i32) {
+        self.update_with(Some((wall_ns, tz_minuteswest, tz_dsttime)));
+    }
+
+    /// Drop every clock's `cycle_last` once the counter has stayed behind the
+    /// timekeeper's for `MAX_BACKWARDS_UPDATES` consecutive updates.
+    ///
+    /// A single backwards read is a CPU lagging the previous one and the
+    /// update is skipped, but a counter that restarted (e.g. kexec without
+    /// `reinit_vdso_data`) stays behind for good. Without this every later
+    /// update would be skipped and interpolated time would freeze; with
+    /// `cycle_last` cleared the next update takes the initialization path.
+    fn check_counter_restart(&mut self, cycle_now: u64) {
+        let tk = self.timekeeper();
+        let prev = tk.cycle_last.load(Ordering::Relaxed);
+        let backwards = tk.clock_mode != ClockMode::None as i32
+            && prev != 0
+            && (cycle_now.wrapping_sub(prev) & tk.mask) > tk.mask >> 1;
+        if !backwards {
+            BACKWARDS_UPDATES.store(0, Ordering::Relaxed);
+            return;
+        }
+        if BACKWARDS_UPDATES.fetch_add(1, Ordering::Relaxed) + 1 >= MAX_BACKWARDS_UPDATES {
+            log::warn!(
+                "vDSO: counter behind cycle_last for {} updates, assuming it restarted",
+                MAX_BACKWARDS_UPDATES
+            );
+            BACKWARDS_UPDATES.store(0, Ordering::Relaxed);
+            self.reset_cycle_last();
+        }
+    }
+
+    /// Publish a new update, with realtime and the timezone taken from
+    /// `settime` instead of the platform if given.
+    fn update_with(&mut self, settime: Option<(u64, i32, i32)>) {
+        self.publish(UpdateSample::now(), settime);
+    }
+
+    /// Publish the update for `sample`, see [`Self::update_with`].
+    fn publish(&mut self, sample: UpdateSample, settime: Option<(u64, i32, i32)>) {
+        if is_frozen() {
+            return;
+        }
+        let cycle_now = sample.cycle_now;
+        self.check_counter_restart(cycle_now);
+        let mono_ns = checked_mono_ns(self.timekeeper(), sample.mono_ns, cycle_now);
+        let wall_ns = match settime {
+            Some((wall_ns, ..)) => {
+                reset_wall_offset((wall_ns as i64).wrapping_sub(mono_ns as i64));
+                wall_ns
+            }
+            None => smoothed_wall_ns(sample.wall_ns, mono_ns),
+        };
+        let smear_ns = leap_smear_ns(mono_ns);
+        let prev_smear_ns = LEAP_SMEAR_LAST_NS.swap(smear_ns, Ordering::Relaxed);
+        let wall_ns = wall_ns.saturating_add_signed(smear_ns);
+        let maxsec = MAX_CONVERSION_SECS.load(Ordering::Relaxed);
+        let mult_shift = if identity_scaling() {
+            (1, 0)
+        } else {
+            clocks_calc_mult_shift(sample.ticks_per_sec, NANOS_PER_SEC, maxsec)
+        };
+        let tk_mult_shift =
+            leap_smear_mult_shift(sample.ticks_per_sec, maxsec).unwrap_or(mult_shift);
+
+        for (i, clk) in self.clock_data.iter_mut().enumerate() {
+            clk.write_seqcount_begin();
+            if i != 0 {
+                update_vdso_clock(clk, cycle_now, wall_ns, mono_ns, mult_shift);
+                clk.write_seqcount_end();
+                continue;
+            }
+            let prev_offset = wall_offset_ns(clk);
+            // Realtime as readers interpolate it right now, which a smear
+            // must not take back.
+            let prev_realtime = (smear_ns != 0 && clk.cycle_last.load(Ordering::Relaxed) != 0)
+                .then(|| clk.hres_time(ClockBase::Realtime, cycle_now));
+            update_vdso_clock(clk, cycle_now, wall_ns, mono_ns, tk_mult_shift);
+            if let Some(prev) = prev_realtime
+                && clk.cycle_last.load(Ordering::Relaxed) == cycle_now
+                && clk.hres_time(ClockBase::Realtime, cycle_now) < prev
+            {
+                clk.time_data[ClockBase::Realtime as usize] = VdsoTimestamp {
+                    sec: prev.sec,
+                    nsec: prev.nsec << clk.shift,
+                };
+            }
+            self.last_update_mono_ns = mono_ns;
+            // The smear moves realtime on purpose; only report steps of the
+            // wall clock itself.
+            self.last_wall_step_ns = match prev_offset {
+                Some(prev) => (wall_offset_ns(clk).unwrap_or(prev)
+                    - prev
+                    - (smear_ns as i128 - prev_smear_ns as i128))
+                    .clamp(i64::MIN as i128, i64::MAX as i128) as i64,
+                None => 0,
+            };
+            if let Some((_, tz_minuteswest, tz_dsttime)) = settime {
+                self.tz_minuteswest = tz_minuteswest;
+                self.tz_dsttime = tz_dsttime;
+            }
+            clk.write_seqcount_end();
+        }
+    }
+}
+
+/// Platform readings an update publishes.
+#[derive(Debug, Clone, Copy)]
+struct UpdateSample {
+    /// Counter value the published bases correspond to.
+    cycle_now: u64,
+    /// Platform monotonic time, in nanoseconds.
+    mono_ns: u64,
+    /// Platform wall time, in nanoseconds.
+    wall_ns: u64,
+    /// Counter frequency `mult`/`shift` are derived from, in Hz.
+    ticks_per_sec: u64,
+}
+
+impl UpdateSample {
+    /// Read the counter and the platform clocks, back to back.
+    fn now() -> Self {
+        Self {
+            cycle_now: current_ticks(),
+            mono_ns: monotonic_time_nanos(),
+            wall_ns: wall_time_nanos(),
+            ticks_per_sec: counter_hz(),
+        }
+    }
+}
+
+/// Return the platform monotonic time `mono_ns`, or the time extrapolated
+/// from the counter if the platform clock did not advance.
+///
+/// Rebasing the published monotonic time to a platform value that did not
+/// move would throw away the time readers already interpolated since the
+/// last update and make `CLOCK_MONOTONIC` go backwards. On every such update
+/// the published base of `tk` is advanced by the counter instead, never
+/// below `mono_ns`. A platform clock that saturates or stops would otherwise
+/// stop every vDSO clock for good, so after `STUCK_MONO_UPDATES` consecutive
+/// updates over which the counter advanced a warning is logged. Without a
+/// real counter scaling there is nothing to extrapolate with, so `mono_ns`
+/// is returned as is.
+fn checked_mono_ns(tk: &VdsoClock, mono_ns: u64, cycle_now: u64) -> u64 {
+    let prev = LAST_PLATFORM_MONO_NS.swap(mono_ns, Ordering::Relaxed);
+    if mono_ns > prev || !tk.is_calibrated() {
+        STUCK_MONO_COUNT.store(0, Ordering::Relaxed);
+        return mono_ns;
+    }
+    if tk.delta_cycles(cycle_now) != 0 {
+        let stuck = STUCK_MONO_COUNT.fetch_add(1, Ordering::Relaxed) + 1;
+        if stuck == STUCK_MONO_UPDATES {
+            log::warn!(
+                "vDSO: platform monotonic time stuck at {} ns for {} updates, extrapolating from \
+                 the counter",
+                mono_ns,
+                stuck
+            );
+        }
+    }
+    let ts = tk.hres_time(ClockBase::Monotonic, cycle_now);
+    ts.sec
+        .saturating_mul(NANOS_PER_SEC)
+        .saturating_add(ts.nsec)
+        .max(mono_ns)
+}
+
+/// Resynchronize the state updates build on with the restored timekeeper
+/// `tk` and return its monotonic time, see [`VdsoTimeData::restore`].
+fn restore_update_state(tk: &VdsoClock) -> u64 {
+    let base_ns = |base: ClockBase| {
+        let ts = tk.time_data[base as usize];
+        ts.sec
+            .saturating_mul(NANOS_PER_SEC)
+            .saturating_add(ts.nsec >> tk.shift)
+    };
+    let mono_ns = base_ns(ClockBase::Monotonic);
+    let offset = wall_offset_ns(tk).map_or(i64::MIN, |offset| {
+        offset.clamp(i64::MIN as i128 + 1, i64::MAX as i128) as i64
+    });
+    reset_wall_offset(offset);
+    SUSPENDED_NS.store(
+        base_ns(ClockBase::Boottime).saturating_sub(mono_ns),
+        Ordering::Relaxed,
+    );
+    LAST_PLATFORM_MONO_NS.store(mono_ns, Ordering::Relaxed);
+    STUCK_MONO_COUNT.store(0, Ordering::Relaxed);
+    BACKWARDS_UPDATES.store(0, Ordering::Relaxed);
+    LEAP_SMEAR_ACTIVE.store(false, Ordering::Release);
+    LEAP_SMEAR_LAST_NS.store(0, Ordering::Relaxed);
+    VDSO_FROZEN.store(false, Ordering::Release);
+    mono_ns
+}
+
+/// Published realtime minus monotonic base of `clk`, in nanoseconds, or
+/// `None` before the first update.
+fn wall_offset_ns(clk: &VdsoClock) -> Option<i128> {
+    let ns =
+        |ts: VdsoTimestamp| ts.sec as i128 * NANOS_PER_SEC as i128 + (ts.nsec >> clk.shift) as i128;
+    let mono = clk.time_data[ClockBase::Monotonic as usize];
+    if mono == VdsoTimestamp::new() {
+        return None;
+    }
+    Some(ns(clk.time_data[ClockBase::Realtime as usize]) - ns(mono))
+}
+
+/// Split `ns` into whole seconds and a sub-second part stored as a
+/// fixed-point value shifted left by `shift`, as the vDSO reader expects.
+///
+/// The result satisfies `sec * NANOS_PER_SEC + (nsec >> shift) == ns`.
+///
+/// Storing involves no rounding: the fraction bits below `shift` are zero, so
+/// `nsec >> shift` recovers the nanoseconds exactly and is always below
+/// `NANOS_PER_SEC`, provided `shift <= MAX_SHIFT`, which
+/// [`VdsoClock::set_scaling`] guarantees. Rounding only happens in the reader,
+/// which truncates the fraction of `base.nsec + delta * mult` when shifting
+/// down, so interpolated times are rounded towards zero by less than 1 ns.
+pub fn timestamp_from_ns(ns: u64, shift: u32) -> VdsoTimestamp {
+    debug_assert!(
+        (ns % NANOS_PER_SEC).leading_zeros() >= shift,
+        "vDSO nsec overflows with shift {shift}"
+    );
+    VdsoTimestamp {
+        sec: ns / NANOS_PER_SEC,
+        nsec: (ns % NANOS_PER_SEC) << shift,
+    }
+}
+
+// `MAX_SHIFT` is exactly the headroom above the largest sub-second part, and
+// the largest shift `clocks_calc_mult_shift` produces (32) is within it, so
+// its bases are stored losslessly.
+const _: () = assert!((NANOS_PER_SEC - 1).leading_zeros() == MAX_SHIFT);
+const _: () = assert!(32 <= MAX_SHIFT);
+
+/// Advance `ts`, stored with the given `shift`, by `ns` nanoseconds.
+fn timestamp_add_ns(ts: VdsoTimestamp, ns: u64, shift: u32) -> VdsoTimestamp {
+    let add = timestamp_from_ns(ns, shift);
+    // Both parts are below one second, but their sum can exceed `u64` for
+    // shifts near `MAX_SHIFT`.
+    let one_sec = (NANOS_PER_SEC as u128) << shift;
+    let nsec = ts.nsec as u128 + add.nsec as u128;
+    VdsoTimestamp {
+        sec: ts.sec + add.sec + (nsec / one_sec) as u64,
+        nsec: (nsec % one_sec) as u64,
+    }
 }
 
 /// Update vDSO clock.
@@ -121,11 +1183,15 @@ pub fn update_vdso_clock(
     mono_ns: u64,
     mult_shift: (u32, u32),
 ) {
-    let prev_cycle = clk.cycle_last.load(Ordering::Relaxed);
     let prev_basetime_ns = clk.time_data[1]
         .sec
         .wrapping_mul(NANOS_PER_SEC)
-        .wrapping_add(clk.time_data[1].nsec);
+        .wrapping_add(clk.time_data[1].nsec >> clk.shift);
+
+    // The coarse bases only need the update's own time, so refresh them
+    // before any branch below can skip the rest of the update.
+    clk.time_data[ClockBase::RealtimeCoarse as usize] = timestamp_from_ns(wall_ns, 0);
+    clk.time_data[ClockBase::MonotonicCoarse as usize] = timestamp_from_ns(mono_ns, 0);
 
     // Check if this is a counter-based clock mode (non-None)
     let is_counter_mode = clk.clock_mode != (ClockMode::None as i32);
@@ -133,48 +1199,95 @@ pub fn update_vdso_clock(
     if is_counter_mode {
         // Counter-based modes: Tsc (x86_64), Csr (riscv64/loongarch64), Cntvct
         // (aarch64)
+        let mask = if identity_scaling() {
+            u64::MAX
+        } else {
+            COUNTER_MASK
+        };
+        if clk.mask != mask {
+            // Deltas and wraps counted in the old width are meaningless in
+            // the new one, so start over.
+            clk.mask = mask;
+            clk.clear_cycle_last();
+        }
+        let prev_cycle = clk.cycle_last.load(Ordering::Relaxed);
+        // A masked delta in the upper half of the counter range is not a
+        // plausible forward step: the counter was read on a CPU lagging the
+        // previous one. Publishing it would make readers see a huge jump.
+        // A counter that stays behind is handled by
+        // `VdsoTimeData::check_counter_restart`.
+        let delta_cycles = cycle_now.wrapping_sub(prev_cycle) & clk.mask;
+        if prev_cycle != 0 && delta_cycles > clk.mask >> 1 {
+            let events = CYCLE_BACKWARDS_EVENTS.fetch_add(1, Ordering::Relaxed) + 1;
+            // Rate-limited: this runs from the timer tick.
+            if events.is_power_of_two() {
+                log::warn!(
+                    "vDSO update skipped: counter went backwards ({} -> {}), {} times so far",
+                    prev_cycle,
+                    cycle_now,
+                    events
+                );
+            }
+            return;
+        }
         if prev_cycle == 0 {
             let (mult, shift) = mult_shift;
-            clk.mult = mult;
-            clk.shift = shift;
-            clk.time_data[1].sec = mono_ns / NANOS_PER_SEC;
-            clk.time_data[1].nsec = (mono_ns % NANOS_PER_SEC) << shift;
-            clk.cycle_last.store(cycle_now, Ordering::Relaxed);
+            clk.set_scaling(mult, shift);
+            clk.time_data[1] = timestamp_from_ns(mono_ns, clk.shift);
+            clk.advance_cycle_last(cycle_now);
         } else {
             let (mult, shift) = mult_shift;
-            if !(mult == u32::MAX && shift == 0) {
-                clk.mult = mult;
-                clk.shift = shift;
-                clk.time_data[1].sec = mono_ns / NANOS_PER_SEC;
-                clk.time_data[1].nsec = (mono_ns % NANOS_PER_SEC) << shift;
-                clk.cycle_last.store(cycle_now, Ordering::Relaxed);
+            if mult_shift != FALLBACK_MULT_SHIFT {
+                clk.set_scaling(mult, shift);
+                clk.time_data[1] = timestamp_from_ns(mono_ns, clk.shift);
+                clk.advance_cycle_last(cycle_now);
             } else {
-                let delta_cycles = (cycle_now.wrapping_sub(prev_cycle)) & clk.mask;
                 let delta_ns = mono_ns.saturating_sub(prev_basetime_ns);
+                // A delta pair implying a frequency more than 2x off the
+                // current scaling is a bad counter read or a migration, not
+                // a real frequency change: keep the previous scaling. Not
+                // checked until a real scaling replaced the fallback.
+                let scaled_ns = clk.cycles_to_ns(delta_cycles);
+                if clk.is_calibrated() && (delta_ns / 2 > scaled_ns || scaled_ns / 2 > delta_ns) {
+                    RECALIBRATION_REJECTS.fetch_add(1, Ordering::Relaxed);
+                    log::warn!(
+                        "vDSO update skipped: {} cycles in {} ns disagree with the scaling ({} ns)",
+                        delta_cycles,
+                        delta_ns,
+                        scaled_ns
+                    );
+                    return;
+                }
                 if delta_cycles != 0 && delta_ns > 0 {
                     let (mult, shift) = clocks_calc_mult_shift(delta_cycles, delta_ns, 1);
-                    clk.mult = mult;
-                    clk.shift = shift;
-                    clk.time_data[1].sec = mono_ns / NANOS_PER_SEC;
-                    clk.time_data[1].nsec = (mono_ns % NANOS_PER_SEC) << shift;
-                    clk.cycle_last.store(cycle_now, Ordering::Relaxed);
+                    clk.set_scaling(mult, shift);
+                    clk.time_data[1] = timestamp_from_ns(mono_ns, clk.shift);
+                    clk.advance_cycle_last(cycle_now);
                 }
             }
         }
     } else {
-        // ClockMode::None - No cycle->ns conversion; store direct monotonic ns.
+        // ClockMode::None - No cycle->ns conversion; store monotonic ns with the
+        // same shifted nsec convention as the other modes.
         clk.mult = 0;
-        clk.time_data[1].sec = mono_ns / NANOS_PER_SEC;
-        clk.time_data[1].nsec = mono_ns % NANOS_PER_SEC;
-        clk.cycle_last.store(0, Ordering::Relaxed);
+        clk.time_data[1] = timestamp_from_ns(mono_ns, clk.shift);
+        clk.clear_cycle_last();
     }
 
-    // Update realtime and boottime entries.
-    let shift = clk.shift;
-    clk.time_data[0].sec = wall_ns / NANOS_PER_SEC;
-    clk.time_data[0].nsec = (wall_ns % NANOS_PER_SEC) << shift;
-    clk.time_data[7].sec = clk.time_data[1].sec;
-    clk.time_data[7].nsec = clk.time_data[1].nsec;
+    // Update realtime, raw and boottime entries. There is no frequency
+    // adjustment of the monotonic clock, so the raw clock equals it.
+    clk.time_data[0] = timestamp_from_ns(wall_ns, clk.shift);
+    clk.time_data[4] = clk.time_data[1];
+    clk.time_data[7] = timestamp_add_ns(
+        clk.time_data[1],
+        SUSPENDED_NS.load(Ordering::Relaxed),
+        clk.shift,
+    );
+    // Boottime is monotonic plus time spent suspended, never less.
+    debug_assert!(
+        clk.time_data[7] >= clk.time_data[1],
+        "vDSO boottime base is behind monotonic"
+    );
 
     if clk.seq.load(Ordering::Relaxed) < 10 {
         let cycle_val = clk.cycle_last.load(Ordering::Relaxed);
@@ -191,9 +1304,20 @@ pub fn update_vdso_clock(
 
 /// Compute multiplier and shift to convert from timer_frequency to
 /// nanos_per_sec.
+///
+/// Returns [`FALLBACK_MULT_SHIFT`] for a zero `from`, which has no
+/// conversion, and `(1, 0)` when `from == to`, e.g. a 1 GHz counter. The
+/// generic search also lands on an exact power of two there, but the identity
+/// makes that explicit and keeps the reader's multiply trivially exact.
 pub fn clocks_calc_mult_shift(from: u64, to: u64, maxsec: u32) -> (u32, u32) {
+    if from == 0 {
+        return FALLBACK_MULT_SHIFT;
+    }
+    if from == to {
+        return (1, 0);
+    }
     // sftacc starts at 32 and is reduced based on the maximum conversion range
-    let mut tmp = ((maxsec as u64).wrapping_mul(from)) >> 32;
+    let mut tmp = ((maxsec as u128) * (from as u128)) >> 32;
     let mut sftacc: i32 = 32;
     while tmp != 0 {
         tmp >>= 1;
@@ -217,6 +1341,663 @@ pub fn clocks_calc_mult_shift(from: u64, to: u64, maxsec: u32) -> (u32, u32) {
             return (mult, sft as u32);
         }
     }
-    // Fallback: return maximum multiplier with shift 0
-    (u32::MAX, 0)
+    FALLBACK_MULT_SHIFT
+}
+
+#[cfg(test)]
+mod tests {
+    use rand_core::RngCore;
+    use rand_pcg::Pcg64Mcg;
+
+    use super::*;
+
+    /// Counter frequencies seen on real hardware and emulators, in Hz.
+    const FREQ_CORPUS: [u64; 12] = [
+        1,
+        32_768,
+        1_000_000,
+        10_000_000,
+        19_200_000,
+        24_000_000,
+        62_500_000,
+        100_000_000,
+        1_000_000_000,
+        2_100_000_000,
+        3_000_000_000,
+        5_000_000_000,
+    ];
+    const MAXSEC_CORPUS: [u32; 4] = [1, 10, 60, 600];
+
+    /// `(from, maxsec)` pairs: the corpus crossed, then random inputs.
+    fn mult_shift_inputs() -> impl Iterator<Item = (u64, u32)> {
+        let mut rng = Pcg64Mcg::new(0);
+        let corpus = FREQ_CORPUS
+            .into_iter()
+            .flat_map(|from| MAXSEC_CORPUS.into_iter().map(move |maxsec| (from, maxsec)));
+        let random = (0..2000).map(move |_| {
+            let from = 1 + rng.next_u64() % 10_000_000_000;
+            let maxsec = 1 + rng.next_u32() % 600;
+            (from, maxsec)
+        });
+        corpus.chain(random)
+    }
+
+    /// Counter deltas within `maxsec` seconds of a `from` Hz counter,
+    /// including both ends of the range.
+    fn deltas(from: u64, maxsec: u32, rng: &mut Pcg64Mcg) -> Vec<u64> {
+        let max = maxsec as u64 * from;
+        [0, 1, max - 1, max]
+            .into_iter()
+            .chain((0..32).map(|_| rng.next_u64() % (max + 1)))
+            .collect()
+    }
+
+    /// Counter frequency of the simulated platform in the update tests.
+    const TEST_HZ: u64 = 24_000_000;
+    /// Simulated wall-minus-monotonic offset.
+    const TEST_WALL_OFFSET_NS: u64 = 1_700_000_000 * NANOS_PER_SEC;
+
+    /// Platform readings at counter value `cycle_now` and monotonic time
+    /// `mono_ns`, with the wall clock a constant offset ahead.
+    fn sample(cycle_now: u64, mono_ns: u64) -> UpdateSample {
+        UpdateSample {
+            cycle_now,
+            mono_ns,
+            wall_ns: mono_ns + TEST_WALL_OFFSET_NS,
+            ticks_per_sec: TEST_HZ,
+        }
+    }
+
+    /// Time of `base` at counter value `cycles`, in plain nanoseconds.
+    fn ns_at(clk: &VdsoClock, base: ClockBase, cycles: u64) -> u64 {
+        let ts = clk.hres_time(base, cycles);
+        ts.sec * NANOS_PER_SEC + ts.nsec
+    }
+
+    fn clock(mult: u32, shift: u32, base_ns: u64) -> VdsoClock {
+        let mut clk = VdsoClock::new();
+        clk.mask = u64::MAX;
+        clk.set_scaling(mult, shift);
+        clk.time_data[ClockBase::Monotonic as usize] = timestamp_from_ns(base_ns, clk.shift);
+        clk
+    }
+
+    #[test]
+    fn timestamp_from_ns_is_lossless_up_to_max_shift() {
+        for ns in [
+            0,
+            1,
+            NANOS_PER_SEC - 1,
+            NANOS_PER_SEC,
+            5 * NANOS_PER_SEC - 1,
+        ] {
+            for shift in 0..=MAX_SHIFT {
+                let ts = timestamp_from_ns(ns, shift);
+                assert_eq!(ts.sec, ns / NANOS_PER_SEC, "{ns} ns, shift {shift}");
+                assert_eq!(
+                    ts.nsec >> shift,
+                    ns % NANOS_PER_SEC,
+                    "{ns} ns, shift {shift}"
+                );
+                // Read back without interpolation: no rounding at all.
+                let clk = clock(0, shift, ns);
+                let back = clk.hres_time(ClockBase::Monotonic, 0);
+                assert_eq!(back.sec * NANOS_PER_SEC + back.nsec, ns, "shift {shift}");
+            }
+        }
+    }
+
+    #[test]
+    fn timestamp_add_ns_carries_at_the_second_boundary() {
+        for shift in 0..=MAX_SHIFT {
+            let ts = timestamp_from_ns(NANOS_PER_SEC - 1, shift);
+            let next = timestamp_add_ns(ts, 1, shift);
+            assert_eq!((next.sec, next.nsec), (1, 0), "shift {shift}");
+            let wrap = timestamp_add_ns(ts, NANOS_PER_SEC - 1, shift);
+            assert_eq!(
+                (wrap.sec, wrap.nsec >> shift),
+                (1, NANOS_PER_SEC - 2),
+                "shift {shift}"
+            );
+        }
+    }
+
+    #[test]
+    fn mult_shift_does_not_overflow_within_maxsec() {
+        let mut rng = Pcg64Mcg::new(1);
+        for (from, maxsec) in mult_shift_inputs() {
+            let (mult, shift) = clocks_calc_mult_shift(from, NANOS_PER_SEC, maxsec);
+            assert!(
+                mult != 0 && shift <= 32,
+                "{from} Hz, {maxsec} s: {mult}/{shift}"
+            );
+            for delta in deltas(from, maxsec, &mut rng) {
+                assert!(
+                    delta as u128 * mult as u128 <= u64::MAX as u128,
+                    "{from} Hz, {maxsec} s: {delta} * {mult} overflows"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn mult_shift_is_monotonic_in_delta() {
+        let mut rng = Pcg64Mcg::new(2);
+        for (from, maxsec) in mult_shift_inputs() {
+            let (mult, shift) = clocks_calc_mult_shift(from, NANOS_PER_SEC, maxsec);
+            let clk = clock(mult, shift, 0);
+            let mut deltas = deltas(from, maxsec, &mut rng);
+            deltas.sort_unstable();
+            for pair in deltas.windows(2) {
+                let (a, b) = (clk.cycles_to_ns(pair[0]), clk.cycles_to_ns(pair[1]));
+                assert!(
+                    a <= b,
+                    "{from} Hz: {} -> {a} ns, {} -> {b} ns",
+                    pair[0],
+                    pair[1]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn mult_shift_error_is_bounded() {
+        let mut rng = Pcg64Mcg::new(3);
+        for (from, maxsec) in mult_shift_inputs() {
+            let (mult, shift) = clocks_calc_mult_shift(from, NANOS_PER_SEC, maxsec);
+            let clk = clock(mult, shift, 0);
+            for delta in deltas(from, maxsec, &mut rng) {
+                let exact = delta as u128 * NANOS_PER_SEC as u128 / from as u128;
+                let got = clk.cycles_to_ns(delta) as u128;
+                // `mult` is rounded to within half a unit of `2^shift`, and
+                // the reader truncates once more.
+                let bound = (delta as u128 >> (shift + 1)) + 1;
+                assert!(
+                    got.abs_diff(exact) <= bound,
+                    "{from} Hz, {maxsec} s, {delta} cycles: {got} ns, exact {exact} ns"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn hres_time_matches_exact_interpolation() {
+        let mut rng = Pcg64Mcg::new(4);
+        for (from, maxsec) in mult_shift_inputs() {
+            let (mult, shift) = clocks_calc_mult_shift(from, NANOS_PER_SEC, maxsec);
+            let base_ns = rng.next_u64() >> 8;
+            let clk = clock(mult, shift, base_ns);
+            for delta in deltas(from, maxsec, &mut rng) {
+                let ts = clk.hres_time(ClockBase::Monotonic, delta);
+                let got = ts.sec as u128 * NANOS_PER_SEC as u128 + ts.nsec as u128;
+                let frac = ((base_ns % NANOS_PER_SEC) as u128) << clk.shift;
+                let exact = (base_ns / NANOS_PER_SEC) as u128 * NANOS_PER_SEC as u128
+                    + ((frac + delta as u128 * clk.mult as u128) >> clk.shift);
+                assert_eq!(got, exact, "{from} Hz, base {base_ns} ns, {delta} cycles");
+                assert!(ts.nsec < NANOS_PER_SEC);
+            }
+        }
+    }
+
+    #[test]
+    fn stuck_platform_monotonic_is_extrapolated_from_the_counter() {
+        let _globals = crate::lock_globals();
+        let mut data = Box::new(VdsoTimeData::new());
+        let mono_ns = 5 * NANOS_PER_SEC;
+        data.publish(sample(1000, mono_ns), None);
+        assert!(data.timekeeper().is_calibrated());
+
+        // The platform clock stays put while the counter runs on.
+        let tick = TEST_HZ / 100;
+        let mut prev = ns_at(data.timekeeper(), ClockBase::Monotonic, 1000);
+        for i in 1..=2 * STUCK_MONO_UPDATES as u64 {
+            let cycles = 1000 + i * tick;
+            data.publish(sample(cycles, mono_ns), None);
+            let now = ns_at(data.timekeeper(), ClockBase::Monotonic, cycles);
+            assert!(now > prev, "update {i}: monotonic stuck at {now} ns");
+            assert_eq!(now, mono_ns + i * 10_000_000, "update {i}");
+            prev = now;
+        }
+    }
+
+    #[test]
+    fn backward_counter_reads_do_not_jump_the_clocks() {
+        let _globals = crate::lock_globals();
+        let tick = TEST_HZ / 100;
+        let tick_ns = 10_000_000;
+        let start = 100 * tick;
+        let mut data = Box::new(VdsoTimeData::new());
+        data.publish(sample(start, NANOS_PER_SEC), None);
+        let mono = ns_at(data.timekeeper(), ClockBase::Monotonic, start);
+        let real = ns_at(data.timekeeper(), ClockBase::Realtime, start);
+
+        // An update on a CPU whose counter lags by a tick is skipped, and
+        // readers on that CPU see the last published time, not a jump.
+        let events = cycle_backwards_events();
+        data.publish(sample(start - tick, NANOS_PER_SEC + tick_ns), None);
+        let tk = data.timekeeper();
+        // Counted by each clock that skipped it.
+        assert_eq!(
+            cycle_backwards_events(),
+            events + data.clock_data.len() as u64
+        );
+        assert_eq!(tk.cycle_last.load(Ordering::Relaxed), start);
+        assert_eq!(ns_at(tk, ClockBase::Monotonic, start - tick), mono);
+        assert_eq!(ns_at(tk, ClockBase::Realtime, start - tick), real);
+
+        // A counter that stays behind restarted, and the update after
+        // `MAX_BACKWARDS_UPDATES` of them starts over from it.
+        for i in 2..=MAX_BACKWARDS_UPDATES as u64 {
+            let mono_ns = NANOS_PER_SEC + i * tick_ns;
+            data.publish(sample(i * tick, mono_ns), None);
+            let tk = data.timekeeper();
+            let cycle_last = tk.cycle_last.load(Ordering::Relaxed);
+            if i < MAX_BACKWARDS_UPDATES as u64 {
+                assert_eq!(cycle_last, start, "update {i}");
+            } else {
+                assert_eq!(cycle_last, i * tick);
+                assert_eq!(ns_at(tk, ClockBase::Monotonic, i * tick), mono_ns);
+            }
+        }
+    }
+
+    #[test]
+    fn switching_from_no_counter_to_a_counter_reinitializes() {
+        let _globals = crate::lock_globals();
+        let tick = TEST_HZ / 100;
+        let mut data = Box::new(VdsoTimeData::new().with_clock_mode(ClockMode::None as i32));
+        data.publish(sample(1000, NANOS_PER_SEC), None);
+        data.publish(sample(1000 + tick, NANOS_PER_SEC + 10_000_000), None);
+        assert!(!data.timekeeper().is_calibrated());
+
+        // The counter comes online much later.
+        let mono_ns = 2 * NANOS_PER_SEC;
+        let cycles = 50 * tick;
+        data.set_clock_mode(ClockMode::from_raw(1).unwrap());
+        data.publish(sample(cycles, mono_ns), None);
+        let maxsec = MAX_CONVERSION_SECS.load(Ordering::Relaxed);
+        for clk in &data.clock_data {
+            assert_eq!(clk.cycle_last.load(Ordering::Relaxed), cycles);
+            assert_eq!(
+                (clk.mult, clk.shift),
+                clocks_calc_mult_shift(TEST_HZ, NANOS_PER_SEC, maxsec)
+            );
+        }
+        let tk = data.timekeeper();
+        assert_eq!(ns_at(tk, ClockBase::Monotonic, cycles), mono_ns);
+        assert_eq!(
+            ns_at(tk, ClockBase::Monotonic, cycles + tick),
+            mono_ns + 10_000_000
+        );
+        data.publish(sample(cycles + tick, mono_ns + 10_000_000), None);
+        assert_eq!(
+            ns_at(data.timekeeper(), ClockBase::Monotonic, cycles + tick),
+            mono_ns + 10_000_000
+        );
+    }
+
+    #[test]
+    fn frozen_clocks_stand_still_until_unfrozen() {
+        let _globals = crate::lock_globals();
+        let tick = TEST_HZ / 100;
+        let tick_ns = 10_000_000;
+        let mut data = Box::new(VdsoTimeData::new());
+        data.publish(sample(1000, NANOS_PER_SEC), None);
+        data.freeze_at(1000 + tick);
+        assert!(is_frozen());
+
+        let frozen_ns = NANOS_PER_SEC + tick_ns;
+        let seq = data.timekeeper().seq.load(Ordering::Relaxed);
+        for i in 2..100 {
+            data.publish(sample(1000 + i * tick, NANOS_PER_SEC + i * tick_ns), None);
+            for at in [1000 + i * tick, u64::MAX >> 2] {
+                let tk = data.timekeeper();
+                assert_eq!(ns_at(tk, ClockBase::Monotonic, at), frozen_ns);
+                assert_eq!(
+                    ns_at(tk, ClockBase::Realtime, at),
+                    frozen_ns + TEST_WALL_OFFSET_NS
+                );
+            }
+        }
+        assert_eq!(data.timekeeper().seq.load(Ordering::Relaxed), seq);
+
+        // Time jumps to the present and runs on.
+        let cycles = 1000 + 200 * tick;
+        let mono_ns = NANOS_PER_SEC + 200 * tick_ns;
+        data.unfreeze_with(sample(cycles, mono_ns));
+        assert!(!is_frozen());
+        let tk = data.timekeeper();
+        assert_eq!(ns_at(tk, ClockBase::Monotonic, cycles), mono_ns);
+        assert_eq!(
+            ns_at(tk, ClockBase::Monotonic, cycles + tick),
+            mono_ns + tick_ns
+        );
+    }
+
+    #[test]
+    fn recalibration_implying_a_tenfold_frequency_is_rejected() {
+        let _globals = crate::lock_globals();
+        let tick = TEST_HZ / 100;
+        let tick_ns = 10_000_000;
+        let maxsec = MAX_CONVERSION_SECS.load(Ordering::Relaxed);
+        let scaling = clocks_calc_mult_shift(TEST_HZ, NANOS_PER_SEC, maxsec);
+        let mut clk = VdsoClock::new();
+        update_vdso_clock(&mut clk, 1000, 0, NANOS_PER_SEC, scaling);
+
+        // Ten ticks' worth of cycles in one tick: a 240 MHz counter.
+        let rejects = recalibration_rejects();
+        let glitch = 1000 + 10 * tick;
+        update_vdso_clock(
+            &mut clk,
+            glitch,
+            0,
+            NANOS_PER_SEC + tick_ns,
+            FALLBACK_MULT_SHIFT,
+        );
+        assert_eq!(recalibration_rejects(), rejects + 1);
+        assert_eq!((clk.mult, clk.shift), scaling);
+        assert_eq!(clk.cycle_last.load(Ordering::Relaxed), 1000);
+
+        // A delta pair matching the counter is still adopted.
+        let cycles = 1000 + 2 * tick;
+        let mono_ns = NANOS_PER_SEC + 2 * tick_ns;
+        update_vdso_clock(&mut clk, cycles, 0, mono_ns, FALLBACK_MULT_SHIFT);
+        assert_eq!(recalibration_rejects(), rejects + 1);
+        assert_eq!(clk.cycle_last.load(Ordering::Relaxed), cycles);
+        assert_eq!(ns_at(&clk, ClockBase::Monotonic, cycles), mono_ns);
+    }
+
+    #[test]
+    fn small_backward_delta_reads_the_base_time() {
+        let maxsec = MAX_CONVERSION_SECS.load(Ordering::Relaxed);
+        let (mult, shift) = clocks_calc_mult_shift(TEST_HZ, NANOS_PER_SEC, maxsec);
+        let base_ns = 5 * NANOS_PER_SEC + 123;
+        let mut clk = clock(mult, shift, base_ns);
+        clk.mask = COUNTER_MASK;
+        // Including a `cycle_last` just past a wrap of the counter.
+        for last in [1_000_000, 3] {
+            clk.cycle_last.store(last, Ordering::Relaxed);
+            for behind in [1, 24, 24_000] {
+                let cycles = last.wrapping_sub(behind) & COUNTER_MASK;
+                assert_eq!(clk.delta_cycles(cycles), 0, "{behind} behind {last}");
+                assert_eq!(ns_at(&clk, ClockBase::Monotonic, cycles), base_ns);
+                assert_eq!(clk.ns_since_update(cycles), 0);
+            }
+            let ahead = (last + 24) & COUNTER_MASK;
+            assert_eq!(ns_at(&clk, ClockBase::Monotonic, ahead), base_ns + 1000);
+        }
+    }
+
+    #[test]
+    fn identity_scaling_reads_the_counter_as_nanoseconds() {
+        let _globals = crate::lock_globals();
+        set_identity_scaling(true);
+        let mut data = Box::new(VdsoTimeData::new());
+        data.publish(sample(1000, NANOS_PER_SEC), None);
+        set_identity_scaling(false);
+
+        let mut rng = Pcg64Mcg::new(195);
+        for clk in &data.clock_data {
+            assert_eq!((clk.mult, clk.shift, clk.mask), (1, 0, u64::MAX));
+            for x in [0, 1, 999_999_999, NANOS_PER_SEC, u64::MAX, rng.next_u64()] {
+                assert_eq!(clk.cycles_to_ns(x), x);
+            }
+        }
+        let tk = data.timekeeper();
+        for x in [1, 999_999_999, 3 * NANOS_PER_SEC + 7, rng.next_u64() >> 8] {
+            assert_eq!(ns_at(tk, ClockBase::Monotonic, 1000 + x), NANOS_PER_SEC + x);
+        }
+    }
+
+    #[test]
+    fn counter_wraps_extend_the_counter_across_updates() {
+        let _globals = crate::lock_globals();
+        let mut clk = VdsoClock::new();
+        let step = (COUNTER_MASK >> 2) + 1;
+        let mut cycles = COUNTER_MASK - 10;
+        let mut total = cycles as u128;
+        update_vdso_clock(&mut clk, cycles, 0, NANOS_PER_SEC, (1, 0));
+        // Three full wraps, a quarter of the range per update.
+        for i in 1..=12 {
+            cycles = cycles.wrapping_add(step) & COUNTER_MASK;
+            total += step as u128;
+            update_vdso_clock(&mut clk, cycles, 0, NANOS_PER_SEC, (1, 0));
+            assert_eq!(clk.extended_cycles(cycles), total, "update {i}");
+        }
+        assert_eq!(clk.counter_wraps, 3);
+
+        clk.set_clock_mode(ClockMode::None);
+        assert_eq!(clk.counter_wraps, 0);
+    }
+
+    #[test]
+    fn skipped_updates_do_not_count_a_wrap() {
+        let _globals = crate::lock_globals();
+        let mut clk = VdsoClock::new();
+        let start = COUNTER_MASK - 100;
+        update_vdso_clock(&mut clk, start, 0, NANOS_PER_SEC, (1, 0));
+        let wrapped = 100;
+        // 201 cycles in 2010 ns disagrees with the 1 ns/cycle scaling.
+        let mono_ns = NANOS_PER_SEC + 2010;
+        for _ in 0..2 {
+            update_vdso_clock(&mut clk, wrapped, 0, mono_ns, FALLBACK_MULT_SHIFT);
+            assert_eq!(clk.counter_wraps, 0);
+            assert_eq!(clk.cycle_last.load(Ordering::Relaxed), start);
+        }
+        update_vdso_clock(
+            &mut clk,
+            wrapped,
+            0,
+            NANOS_PER_SEC + 201,
+            FALLBACK_MULT_SHIFT,
+        );
+        assert_eq!(clk.counter_wraps, 1);
+        assert_eq!(
+            clk.extended_cycles(wrapped),
+            start as u128 + 201,
+            "wrap counted once"
+        );
+    }
+
+    #[test]
+    fn try_read_gives_up_on_a_writer_that_never_finishes() {
+        let _globals = crate::lock_globals();
+        let clk = VdsoClock::new();
+        clk.write_seqcount_begin();
+        let retries = seqlock_read_retries();
+        assert_eq!(clk.try_read(100, |clk| clk.mult), Err(AxError::TimedOut));
+        assert_eq!(seqlock_read_retries() - retries, 100);
+        assert_eq!(clk.try_read(0, |clk| clk.mult), Err(AxError::TimedOut));
+
+        clk.write_seqcount_end();
+        assert_eq!(clk.try_read(1, |clk| clk.mult), Ok(0));
+    }
+
+    #[test]
+    fn platform_counter_frequency_wins_when_known() {
+        assert_eq!(authoritative_counter_hz(24_000_000, 24_000_000), 24_000_000);
+        assert_eq!(authoritative_counter_hz(19_200_000, 24_000_000), 24_000_000);
+        assert_eq!(authoritative_counter_hz(0, 24_000_000), 24_000_000);
+        assert_eq!(authoritative_counter_hz(19_200_000, 0), 19_200_000);
+    }
+
+    #[test]
+    fn updates_use_the_chosen_counter_frequency() {
+        let _globals = crate::lock_globals();
+        set_counter_hz(19_200_000);
+        assert_eq!(counter_hz(), 19_200_000);
+        let mut data = Box::new(VdsoTimeData::new());
+        let hz = counter_hz();
+        let sample = UpdateSample {
+            ticks_per_sec: hz,
+            ..sample(1000, NANOS_PER_SEC)
+        };
+        data.publish(sample, None);
+        set_counter_hz(0);
+        let tk = data.timekeeper();
+        let maxsec = MAX_CONVERSION_SECS.load(Ordering::Relaxed);
+        let expected = clocks_calc_mult_shift(19_200_000, NANOS_PER_SEC, maxsec);
+        assert_eq!((tk.mult, tk.shift), expected);
+    }
+
+    #[test]
+    fn wall_offset_filter_drops_skewed_samples() {
+        let _globals = crate::lock_globals();
+        let mut rng = Pcg64Mcg::new(119);
+        let offset = TEST_WALL_OFFSET_NS as i64;
+        let mono_ns = NANOS_PER_SEC;
+        reset_wall_offset(i64::MIN);
+        smoothed_wall_ns(mono_ns + offset as u64, mono_ns);
+        // The wall clock is read late by up to 2 us, and every seventh
+        // sample by almost the whole jitter bound.
+        for i in 1..1000u64 {
+            let late = if i % 7 == 0 {
+                WALL_OFFSET_JITTER_NS - 1
+            } else {
+                rng.next_u64() % 2_000
+            };
+            let mono = mono_ns + i * 10_000_000;
+            let wall = smoothed_wall_ns(mono + offset as u64 + late, mono);
+            let published = wall as i64 - mono as i64 - offset;
+            assert!(
+                (0..2_000).contains(&published),
+                "update {i}: {published} ns off"
+            );
+        }
+    }
+
+    #[test]
+    fn wall_offset_filter_follows_a_slew_one_update_behind() {
+        let _globals = crate::lock_globals();
+        let offset = TEST_WALL_OFFSET_NS as i64;
+        let drift = 500;
+        reset_wall_offset(i64::MIN);
+        for i in 0..100i64 {
+            let mono = NANOS_PER_SEC + i as u64 * 10_000_000;
+            let sample = offset + i * drift;
+            let published =
+                smoothed_wall_ns(mono.wrapping_add_signed(sample), mono) as i64 - mono as i64;
+            assert_eq!(published, offset + (i - 1).max(0) * drift, "update {i}");
+        }
+        // A step beyond the jitter bound is taken at once.
+        let mono = 2 * NANOS_PER_SEC;
+        let stepped = offset - NANOS_PER_SEC as i64;
+        assert_eq!(
+            smoothed_wall_ns(mono.wrapping_add_signed(stepped), mono) as i64 - mono as i64,
+            stepped
+        );
+        reset_wall_offset(i64::MIN);
+    }
+
+    #[test]
+    fn restore_resynchronizes_the_update_state() {
+        let _globals = crate::lock_globals();
+        let tick = TEST_HZ / 100;
+        let mono_ns = 7 * NANOS_PER_SEC;
+        let mut stashed = Box::new(VdsoTimeData::new());
+        account_suspend(2 * NANOS_PER_SEC);
+        stashed.publish(sample(1000, mono_ns), None);
+        SUSPENDED_NS.store(0, Ordering::Relaxed);
+
+        let mut data = Box::new(VdsoTimeData::new());
+        data.freeze();
+        set_leap_smear_at(true, 500_000, 0);
+        data.restore(&stashed.clock_data, -60, 1);
+        assert!(!is_frozen());
+        assert_eq!(data.timezone(), (-60, 1));
+        assert_eq!(data.last_update_mono_ns, mono_ns);
+
+        // The platform monotonic clock restarted from 0 while the counter and
+        // the wall clock kept going: the update carries on from the stashed
+        // time, with the stashed suspend time and wall offset.
+        let now = 1000 + tick;
+        let wall_ns = mono_ns + 10_000_000 + TEST_WALL_OFFSET_NS;
+        data.publish(
+            UpdateSample {
+                wall_ns,
+                ..sample(now, 0)
+            },
+            None,
+        );
+        let tk = data.timekeeper();
+        let mono = ns_at(tk, ClockBase::Monotonic, now);
+        assert_eq!(mono, mono_ns + 10_000_000);
+        assert_eq!(
+            ns_at(tk, ClockBase::Boottime, now),
+            mono + 2 * NANOS_PER_SEC
+        );
+        assert_eq!(
+            ns_at(tk, ClockBase::Realtime, now),
+            mono + TEST_WALL_OFFSET_NS
+        );
+        assert_eq!(data.last_wall_step_ns, 0);
+        SUSPENDED_NS.store(0, Ordering::Relaxed);
+        LAST_PLATFORM_MONO_NS.store(0, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn realtime_never_goes_back_during_a_leap_smear() {
+        let _globals = crate::lock_globals();
+        let tick = TEST_HZ / 100;
+        let tick_ns = 10_000_000;
+        for rate in [-500_000i64, -11_574, 11_574, 500_000] {
+            reset_wall_offset(i64::MIN);
+            let mut data = Box::new(VdsoTimeData::new());
+            let start_ns = NANOS_PER_SEC;
+            set_leap_smear_at(true, rate, start_ns);
+            data.publish(sample(1000, start_ns), None);
+            let start_real = ns_at(data.timekeeper(), ClockBase::Realtime, 1000);
+
+            let mut prev_real = start_real;
+            let mut prev_mono = start_ns;
+            let updates = 200;
+            for i in 1..=updates {
+                let cycles = 1000 + i * tick;
+                // Readers just before and right after each update.
+                for at in [cycles - tick / 2, cycles - 1] {
+                    let tk = data.timekeeper();
+                    let real = ns_at(tk, ClockBase::Realtime, at);
+                    let mono = ns_at(tk, ClockBase::Monotonic, at);
+                    assert!(
+                        real >= prev_real,
+                        "rate {rate}, update {i}: realtime went back"
+                    );
+                    assert!(
+                        mono >= prev_mono,
+                        "rate {rate}, update {i}: monotonic went back"
+                    );
+                    (prev_real, prev_mono) = (real, mono);
+                }
+                data.publish(sample(cycles, start_ns + i * tick_ns), None);
+                let tk = data.timekeeper();
+                let real = ns_at(tk, ClockBase::Realtime, cycles);
+                let mono = ns_at(tk, ClockBase::Monotonic, cycles);
+                assert!(
+                    real >= prev_real,
+                    "rate {rate}, update {i}: realtime went back"
+                );
+                assert_eq!(mono, start_ns + i * tick_ns, "rate {rate}, update {i}");
+                assert!(
+                    data.last_wall_step_ns.abs() <= 1,
+                    "rate {rate}, update {i}: smear reported as a {} ns step",
+                    data.last_wall_step_ns
+                );
+                (prev_real, prev_mono) = (real, mono);
+            }
+
+            let elapsed = updates * tick_ns;
+            let smeared = elapsed as i64 + elapsed as i64 * rate / NANOS_PER_SEC as i64;
+            let advanced = (prev_real - start_real) as i64;
+            assert!(
+                advanced.abs_diff(smeared) <= 1_000,
+                "rate {rate}: realtime advanced {advanced} ns, expected {smeared} ns"
+            );
+            set_leap_smear_at(false, 0, 0);
+            data.publish(
+                sample(1000 + (updates + 1) * tick, start_ns + elapsed + tick_ns),
+                None,
+            );
+        }
+        LEAP_SMEAR_LAST_NS.store(0, Ordering::Relaxed);
+    }
 }
@@ -1,9 +1,53 @@
 pub const VVAR_PAGES: usize = 6;
 pub const PVCLOCK_MAX_CPUS: usize = 128;
 
+/// Number of clock bases in `VdsoClock::time_data` (`CLOCK_TAI + 1` in the
+/// kernel headers the bundled vDSO was built against).
+pub const VDSO_BASES: usize = 12;
+
+/// Offset of the vDSO clock data within the first VVAR page.
+///
+/// Linux 6.15+ places `struct vdso_time_data` at the start of the page. Older
+/// kernels put `_vdso_data` at offset 128 (`arch/x86/include/asm/vvar.h`);
+/// the `x86-legacy-vvar` feature selects that layout, padding the start of
+/// `VdsoTimeData`, for a vDSO image built against those kernels.
+#[cfg(not(feature = "x86-legacy-vvar"))]
+pub const X86_VDSO_CLOCKS_OFFSET: usize = 0;
+#[cfg(feature = "x86-legacy-vvar")]
+pub const X86_VDSO_CLOCKS_OFFSET: usize = 128;
+
+/// VVAR page holding the pvclock array, following the Linux 6.15+ order:
+/// time data, timens, rng, arch data, pvclock, hvclock.
+pub const PVCLOCK_PAGE: usize = 4;
+
+/// Valid bits of the hardware counter read by the vDSO.
+///
+/// The TSC is a full 64-bit counter (Linux `CLOCKSOURCE_MASK(64)` in
+/// `arch/x86/kernel/tsc.c`).
+pub const COUNTER_MASK: u64 = u64::MAX;
+
 #[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ClockMode {
     None,
     Tsc,
     Pvclock,
 }
+
+impl ClockMode {
+    /// Convert a raw `clock_mode` value back to a known mode.
+    pub const fn from_raw(mode: i32) -> Option<Self> {
+        match mode {
+            0 => Some(Self::None),
+            1 => Some(Self::Tsc),
+            2 => Some(Self::Pvclock),
+            _ => None,
+        }
+    }
+}
+
+// Values shared with the userspace vDSO (`VDSO_CLOCKMODE_*` in
+// `arch/x86/include/asm/vdso/clocksource.h`).
+const _: () = assert!(ClockMode::None as i32 == 0);
+const _: () = assert!(ClockMode::Tsc as i32 == 1);
+const _: () = assert!(ClockMode::Pvclock as i32 == 2);
@@ -1,16 +1,43 @@
+use core::{
+    mem::{align_of, offset_of, size_of},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
 use crate::{
-    config::ClockMode,
-    vdso_time_data::VdsoTimeData,
-    x86_64::{config::PVCLOCK_MAX_CPUS, pvclock_data::PvClockTimeInfo},
+    config::{ClockMode, VVAR_PAGES},
+    vdso::VvarLayout,
+    vdso_time_data::{VdsoClock, VdsoTimeData},
+    x86_64::{
+        config::{PVCLOCK_MAX_CPUS, PVCLOCK_PAGE, X86_VDSO_CLOCKS_OFFSET},
+        pvclock_data::{PVCLOCK_GUEST_STOPPED, PVCLOCK_TSC_STABLE_BIT, PvClockTimeInfo},
+    },
 };
 
+/// Set once [`VdsoData::enable_pvclock`] registered the pvclock page.
+static PVCLOCK_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Bytes between the end of the time data and the pvclock page.
+const PVCLOCK_PAD: usize = PVCLOCK_PAGE * 4096 - size_of::<VdsoTimeData>();
+
 #[repr(C)]
 pub struct VdsoData {
     pub time_data: VdsoTimeData,
-    pub _pad: [u8; 3 * 4096],
+    pub _pad: [u8; PVCLOCK_PAD],
     pub pvclock: [PvClockTimeInfo; PVCLOCK_MAX_CPUS],
 }
 
+// The data page is mapped to userspace, so it must start on a page boundary.
+const _: () = assert!(align_of::<VdsoData>() >= 4096);
+// `VdsoClock` layout read by the bundled vDSO, including `max_cycles`.
+const _: () = assert!(size_of::<VdsoClock>() == 240);
+// The clocks the bundled vDSO reads at `X86_VDSO_CLOCKS_OFFSET` are the ones
+// the shared update writes.
+const _: () = assert!(
+    offset_of!(VdsoData, time_data) + offset_of!(VdsoTimeData, clock_data)
+        == X86_VDSO_CLOCKS_OFFSET
+);
+const _: () = assert!(offset_of!(VdsoData, pvclock) == PVCLOCK_PAGE * 4096);
+
 impl Default for VdsoData {
     fn default() -> Self {
         Self::new()
@@ -18,15 +45,30 @@ impl Default for VdsoData {
 }
 
 impl VdsoData {
+    /// VVAR pages in the Linux 6.15+ order; the timens, rng and arch pages
+    /// lie in `_pad` and stay zeroed, and the hvclock page is not backed.
+    pub const VVAR_LAYOUT: VvarLayout = VvarLayout {
+        total_pages: VVAR_PAGES,
+        backed_pages: size_of::<Self>().div_ceil(4096),
+        time_data: offset_of!(Self, time_data) / 4096,
+        timens: 1,
+        rng: 2,
+        arch: 3,
+        pvclock: Some(offset_of!(Self, pvclock) / 4096),
+    };
+
     pub const fn new() -> Self {
         Self {
             time_data: VdsoTimeData::new(),
-            _pad: [0; 3 * 4096],
+            _pad: [0; PVCLOCK_PAD],
             pvclock: [PvClockTimeInfo::new(); PVCLOCK_MAX_CPUS],
         }
     }
 
     pub fn time_update(&mut self) {
+        if PVCLOCK_ENABLED.load(Ordering::Relaxed) {
+            self.apply_pvclock_flags();
+        }
         self.time_data.update();
     }
 
@@ -34,20 +76,58 @@ impl VdsoData {
     pub fn enable_pvclock(&mut self) {
         register_pvclock(0);
         self.time_data.set_pvclock_mode();
+        PVCLOCK_ENABLED.store(true, Ordering::Relaxed);
         log::info!("vDSO pvclock support enabled");
     }
+
+    /// React to the flags the hypervisor set in the boot CPU's pvclock page.
+    ///
+    /// `PVCLOCK_GUEST_STOPPED` (e.g. after live migration) resets the clocks
+    /// so the next update re-initializes from scratch. Without
+    /// `PVCLOCK_TSC_STABLE_BIT` the TSC cannot be used across CPUs, so the
+    /// clocks fall back to `ClockMode::None` and userspace to the syscall.
+    fn apply_pvclock_flags(&mut self) {
+        let flags_ptr = core::ptr::addr_of_mut!(self.pvclock[0].pvti.flags);
+        let flags = unsafe { flags_ptr.read_volatile() };
+        if flags & PVCLOCK_GUEST_STOPPED != 0 {
+            log::info!("pvclock: guest was stopped, resetting vDSO clocks");
+            self.time_data.reset_cycle_last();
+            unsafe { flags_ptr.write_volatile(flags & !PVCLOCK_GUEST_STOPPED) };
+        }
+        if flags & PVCLOCK_TSC_STABLE_BIT != 0 {
+            self.time_data.set_pvclock_mode();
+        } else {
+            self.time_data.set_clock_mode(ClockMode::None);
+        }
+    }
 }
 
 impl VdsoTimeData {
     pub fn set_pvclock_mode(&mut self) {
-        for clk in self.clock_data.iter_mut() {
-            clk.clock_mode = ClockMode::Pvclock as i32;
-        }
+        self.set_clock_mode(ClockMode::Pvclock);
+    }
+}
+
+/// Read the TSC, the counter the vDSO interpolates with.
+pub fn read_arch_counter() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// Read the TSC as the vDSO's `__arch_get_hw_counter` does, with the
+/// `lfence` of `rdtsc_ordered`.
+///
+/// `rdtsc` is not ordered against earlier loads, so without the fence it
+/// may execute before the seqlock load of `cycle_last` and return a value
+/// older than the one it is subtracted from, making time run backwards.
+pub fn read_hw_counter_fenced() -> u64 {
+    unsafe {
+        core::arch::x86_64::_mm_lfence();
+        core::arch::x86_64::_rdtsc()
     }
 }
 
 fn register_pvclock(cpu_id: usize) {
-    let base = crate::vdso::vdso_data_paddr() as u64 + 4 * 4096;
+    let base = (crate::vdso::vdso_data_paddr() + offset_of!(VdsoData, pvclock)) as u64;
     let offset = cpu_id * core::mem::size_of::<crate::x86_64::pvclock_data::PvClockTimeInfo>();
     let paddr = base + offset as u64;
     crate::x86_64::pvclock_data::register_kvm_clock(paddr);